@@ -0,0 +1,249 @@
+//! # Clipboard Module
+//!
+//! Handles copying the prompt to the clipboard. The native clipboard doesn't work on servers
+//! without a display, so the copy falls back on the OSC 52 terminal escape sequence and on the
+//! `wl-copy`, `xclip` and `xsel` commands. A backend can also be forced.
+
+use anyhow::{anyhow, Context, Result};
+use arboard::Clipboard;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::env;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+/// Largest base64 payload copied with the OSC 52 escape sequence. Many terminal emulators drop
+/// larger sequences without telling, so the copy would seem to succeed.
+const OSC52_MAX_BYTES: usize = 100_000;
+
+/// A way of copying text to the clipboard.
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClipboardBackend {
+    /// Try the backends in order until one works.
+    #[default]
+    Auto,
+    /// The system clipboard of the desktop environment.
+    Native,
+    /// The OSC 52 terminal escape sequence, which the terminal emulator copies to the clipboard of
+    /// the local machine, including over SSH.
+    Osc52,
+    /// The Wayland `wl-copy` command.
+    WlCopy,
+    /// The X11 `xclip` command.
+    Xclip,
+    /// The X11 `xsel` command.
+    Xsel,
+}
+
+impl fmt::Display for ClipboardBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Auto => write!(f, "auto"),
+            Self::Native => write!(f, "native clipboard"),
+            Self::Osc52 => write!(f, "OSC 52"),
+            Self::WlCopy => write!(f, "wl-copy"),
+            Self::Xclip => write!(f, "xclip"),
+            Self::Xsel => write!(f, "xsel"),
+        }
+    }
+}
+
+/// Copies text to the clipboard.
+///
+/// With the auto backend, the native clipboard is tried first, then the clipboard commands of the
+/// display server, and the OSC 52 escape sequence last. Over SSH the OSC 52 escape sequence is
+/// tried before the commands, which would copy to the clipboard of the server.
+///
+/// ### Arguments
+///
+/// - `content`: The text to copy.
+/// - `backend`: The backend to use.
+///
+/// ### Returns
+///
+/// - `Result<ClipboardBackend>`: The backend that copied the text, or an error if no backend
+///   could.
+///
+pub fn copy_to_clipboard(content: &str, backend: ClipboardBackend) -> Result<ClipboardBackend> {
+    if backend != ClipboardBackend::Auto {
+        return copy_with(content, backend).map(|_| backend);
+    }
+
+    let mut errors = Vec::new();
+    for backend in auto_backends(|name| env::var_os(name).is_some()) {
+        match copy_with(content, backend) {
+            Ok(()) => return Ok(backend),
+            Err(e) => errors.push(format!("{}: {:#}", backend, e)),
+        }
+    }
+    Err(anyhow!(
+        "No clipboard backend available ({})",
+        errors.join("; ")
+    ))
+}
+
+/// The backends tried in order by the auto backend.
+///
+/// ### Arguments
+///
+/// - `is_set`: Whether an environment variable is set.
+///
+/// ### Returns
+///
+/// - `Vec<ClipboardBackend>`: The backends to try, in order.
+///
+fn auto_backends(is_set: impl Fn(&str) -> bool) -> Vec<ClipboardBackend> {
+    let over_ssh = is_set("SSH_TTY") || is_set("SSH_CONNECTION");
+    let mut backends = vec![ClipboardBackend::Native];
+    if over_ssh {
+        backends.push(ClipboardBackend::Osc52);
+    }
+    if is_set("WAYLAND_DISPLAY") {
+        backends.push(ClipboardBackend::WlCopy);
+    }
+    if is_set("DISPLAY") {
+        backends.extend([ClipboardBackend::Xclip, ClipboardBackend::Xsel]);
+    }
+    if !over_ssh {
+        backends.push(ClipboardBackend::Osc52);
+    }
+    backends
+}
+
+/// Copies text to the clipboard with a single backend.
+///
+/// ### Arguments
+///
+/// - `content`: The text to copy.
+/// - `backend`: The backend to use, other than auto.
+///
+/// ### Returns
+///
+/// - `Result<()>`: Unit on success, or an error if the backend failed.
+///
+fn copy_with(content: &str, backend: ClipboardBackend) -> Result<()> {
+    match backend {
+        ClipboardBackend::Auto => copy_to_clipboard(content, backend).map(|_| ()),
+        ClipboardBackend::Native => {
+            let mut clipboard = Clipboard::new().context("Failed to initialize the clipboard")?;
+            clipboard
+                .set_text(content.to_owned())
+                .context("Failed to set the clipboard text")
+        }
+        ClipboardBackend::Osc52 => copy_osc52(content),
+        ClipboardBackend::WlCopy => copy_command(content, "wl-copy", &[]),
+        ClipboardBackend::Xclip => copy_command(content, "xclip", &["-selection", "clipboard"]),
+        ClipboardBackend::Xsel => copy_command(content, "xsel", &["--clipboard", "--input"]),
+    }
+}
+
+/// Copies text with the OSC 52 escape sequence, written to the controlling terminal.
+///
+/// The terminal emulator doesn't acknowledge the sequence, so this only fails if the text is too
+/// large for the terminal or there is no terminal to write to.
+///
+/// ### Arguments
+///
+/// - `content`: The text to copy.
+///
+/// ### Returns
+///
+/// - `Result<()>`: Unit on success, or an error if the text is too large or there is no terminal.
+///
+fn copy_osc52(content: &str) -> Result<()> {
+    let payload = STANDARD.encode(content);
+    if payload.len() > OSC52_MAX_BYTES {
+        return Err(anyhow!(
+            "The text is {} bytes once encoded, over the {} bytes terminals accept",
+            payload.len(),
+            OSC52_MAX_BYTES
+        ));
+    }
+    let mut sequence = format!("\x1b]52;c;{}\x07", payload);
+    // tmux only forwards the sequence to the outer terminal inside a passthrough sequence.
+    if env::var_os("TMUX").is_some() {
+        sequence = format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"));
+    }
+
+    match OpenOptions::new().write(true).open("/dev/tty") {
+        Ok(mut tty) => tty.write_all(sequence.as_bytes())?,
+        Err(_) if io::stderr().is_terminal() => io::stderr().write_all(sequence.as_bytes())?,
+        Err(_) => return Err(anyhow!("No terminal to write the escape sequence to")),
+    }
+    Ok(())
+}
+
+/// Copies text by piping it to a clipboard command.
+///
+/// ### Arguments
+///
+/// - `content`: The text to copy.
+/// - `program`: The clipboard command.
+/// - `args`: The arguments of the command.
+///
+/// ### Returns
+///
+/// - `Result<()>`: Unit on success, or an error if the command is missing or failed.
+///
+fn copy_command(content: &str, program: &str, args: &[&str]) -> Result<()> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to run {}", program))?;
+    child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow!("Failed to open the stdin of {}", program))?
+        .write_all(content.as_bytes())
+        .with_context(|| format!("Failed to write to {}", program))?;
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(anyhow!("{} exited with {}", program, status));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ClipboardBackend::*;
+
+    fn backends_with(vars: &[&str]) -> Vec<ClipboardBackend> {
+        auto_backends(|name| vars.contains(&name))
+    }
+
+    #[test]
+    fn tries_the_terminal_last_on_a_desktop() {
+        assert_eq!(backends_with(&[]), [Native, Osc52]);
+        assert_eq!(backends_with(&["WAYLAND_DISPLAY"]), [Native, WlCopy, Osc52]);
+        assert_eq!(backends_with(&["DISPLAY"]), [Native, Xclip, Xsel, Osc52]);
+        assert_eq!(
+            backends_with(&["WAYLAND_DISPLAY", "DISPLAY"]),
+            [Native, WlCopy, Xclip, Xsel, Osc52]
+        );
+    }
+
+    #[test]
+    fn tries_the_terminal_first_over_ssh() {
+        assert_eq!(backends_with(&["SSH_TTY"]), [Native, Osc52]);
+        assert_eq!(
+            backends_with(&["SSH_CONNECTION", "DISPLAY"]),
+            [Native, Osc52, Xclip, Xsel]
+        );
+    }
+
+    #[test]
+    fn rejects_text_too_large_for_osc52() {
+        let content = "a".repeat(OSC52_MAX_BYTES);
+        let error = copy_osc52(&content).unwrap_err().to_string();
+        assert!(error.contains("133336 bytes once encoded"));
+    }
+}
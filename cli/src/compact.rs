@@ -0,0 +1,649 @@
+//! # Compact Module
+//!
+//! Handles the comment stripping and whitespace compaction of the file content. The comment
+//! syntax is picked from the file extension and the lines keep their original line numbers, the
+//! lines left empty by the stripping are removed.
+
+use crate::files::SourceLine;
+use std::borrow::Cow;
+
+/// The comment syntax of a language.
+#[derive(Debug)]
+struct CommentSyntax {
+    /// Line comment markers.
+    line: &'static [&'static str],
+    /// Block comment start and end markers.
+    block: Option<(&'static str, &'static str)>,
+    /// Quote characters of the strings.
+    quotes: &'static [char],
+    /// Whether the quoted strings can span multiple lines. Otherwise they only continue on the
+    /// next line after a trailing backslash.
+    quotes_span_lines: bool,
+    /// Delimiters of the strings that can span multiple lines, with backslash escapes.
+    multiline_quotes: &'static [&'static str],
+    /// Delimiters of the raw strings that can span multiple lines, without escapes.
+    raw_quotes: &'static [&'static str],
+    /// Whether the language has Rust raw strings (`r#"..."#`) and char literals, where a `'`
+    /// that doesn't close a char literal starts a lifetime.
+    rust_literals: bool,
+    /// Whether the language has JavaScript regular expression literals.
+    regex_literals: bool,
+}
+
+impl CommentSyntax {
+    /// Syntax without strings, to build the other syntaxes from.
+    const NONE: CommentSyntax = CommentSyntax {
+        line: &[],
+        block: None,
+        quotes: &[],
+        quotes_span_lines: false,
+        multiline_quotes: &[],
+        raw_quotes: &[],
+        rust_literals: false,
+        regex_literals: false,
+    };
+}
+
+/// Syntax of the languages using `//` and `/* */` comments.
+const C_STYLE: CommentSyntax = CommentSyntax {
+    line: &["//"],
+    block: Some(("/*", "*/")),
+    quotes: &['"', '\''],
+    ..CommentSyntax::NONE
+};
+
+/// Syntax of Go, with multi-line raw strings in backticks.
+const GO: CommentSyntax = CommentSyntax {
+    raw_quotes: &["`"],
+    ..C_STYLE
+};
+
+/// Syntax of Rust, where strings can span multiple lines and `'` is also used for lifetimes.
+const RUST: CommentSyntax = CommentSyntax {
+    line: &["//"],
+    block: Some(("/*", "*/")),
+    quotes: &['"'],
+    quotes_span_lines: true,
+    rust_literals: true,
+    ..CommentSyntax::NONE
+};
+
+/// Syntax of JavaScript and TypeScript, with multi-line template literals and regular
+/// expression literals.
+const JAVASCRIPT: CommentSyntax = CommentSyntax {
+    multiline_quotes: &["`"],
+    regex_literals: true,
+    ..C_STYLE
+};
+
+/// Syntax of CSS, which only has block comments.
+const CSS: CommentSyntax = CommentSyntax {
+    block: Some(("/*", "*/")),
+    quotes: &['"', '\''],
+    ..CommentSyntax::NONE
+};
+
+/// Syntax of the languages using `#` comments.
+const HASH: CommentSyntax = CommentSyntax {
+    line: &["#"],
+    quotes: &['"', '\''],
+    ..CommentSyntax::NONE
+};
+
+/// Syntax of Python, with multi-line triple quoted strings.
+const PYTHON: CommentSyntax = CommentSyntax {
+    multiline_quotes: &["\"\"\"", "'''"],
+    ..HASH
+};
+
+/// Syntax of the languages using `--` comments.
+const DOUBLE_DASH: CommentSyntax = CommentSyntax {
+    line: &["--"],
+    quotes: &['"', '\''],
+    ..CommentSyntax::NONE
+};
+
+/// Syntax of the markup languages.
+const MARKUP: CommentSyntax = CommentSyntax {
+    block: Some(("<!--", "-->")),
+    ..CommentSyntax::NONE
+};
+
+/// Looks up the comment syntax from a file extension.
+fn comment_syntax(extension: &str) -> Option<&'static CommentSyntax> {
+    match extension {
+        "rs" => Some(&RUST),
+        "go" => Some(&GO),
+        "c" | "h" | "cc" | "cpp" | "cxx" | "hpp" | "hh" | "java" | "cs" | "swift" | "kt"
+        | "kts" | "scala" | "dart" | "proto" | "scss" | "less" => Some(&C_STYLE),
+        "js" | "jsx" | "mjs" | "cjs" | "ts" | "tsx" | "mts" | "cts" => Some(&JAVASCRIPT),
+        "css" => Some(&CSS),
+        "py" | "pyi" => Some(&PYTHON),
+        "rb" | "sh" | "bash" | "zsh" | "fish" | "yaml" | "yml" | "toml" | "pl" | "r" | "ex"
+        | "exs" | "nix" | "tf" | "cfg" | "conf" => Some(&HASH),
+        "sql" | "lua" | "hs" => Some(&DOUBLE_DASH),
+        "html" | "htm" | "xml" | "svg" | "vue" => Some(&MARKUP),
+        _ => None,
+    }
+}
+
+/// The lexer state carried over from one line to the next.
+#[derive(Debug, Default, PartialEq)]
+enum State {
+    /// Outside of comments and strings.
+    #[default]
+    Code,
+    /// Inside a block comment.
+    BlockComment,
+    /// Inside a string.
+    String {
+        /// The closing delimiter.
+        close: String,
+        /// Whether backslash escapes apply.
+        escapes: bool,
+        /// Whether the string can continue on the next line without a trailing backslash.
+        spans_lines: bool,
+    },
+}
+
+/// Removes the comments from the file lines.
+///
+/// Lines that only contained comments are removed, the other lines keep their line number. Files
+/// with an unknown extension are returned unchanged.
+///
+/// ### Arguments
+///
+/// - `lines`: The file lines.
+/// - `extension`: The file extension, used to pick the comment syntax.
+///
+/// ### Returns
+///
+/// - `Vec<SourceLine>`: The lines without comments.
+///
+pub fn strip_comments<'a>(lines: Vec<SourceLine<'a>>, extension: &str) -> Vec<SourceLine<'a>> {
+    let Some(syntax) = comment_syntax(extension) else {
+        return lines;
+    };
+
+    let mut state = State::Code;
+    lines
+        .into_iter()
+        .filter_map(|line| {
+            // Keep the shebang line.
+            if line.number == Some(1) && line.text.starts_with("#!") {
+                return Some(line);
+            }
+            let (stripped, had_comment) = strip_line(&line.text, syntax, &mut state);
+            if !had_comment {
+                Some(line)
+            } else if stripped.trim().is_empty() {
+                None
+            } else {
+                Some(SourceLine {
+                    number: line.number,
+                    text: Cow::Owned(stripped.trim_end().to_owned()),
+                })
+            }
+        })
+        .collect()
+}
+
+/// Removes the comments from a single line.
+///
+/// When the lexer state is ambiguous at the end of the line, such as a quote that isn't closed
+/// in a language where strings don't span lines, the line is returned unchanged rather than
+/// risking the removal of code.
+///
+/// ### Arguments
+///
+/// - `line`: The line.
+/// - `syntax`: The comment syntax of the language.
+/// - `state`: The lexer state at the start of the line, updated for the next line.
+///
+/// ### Returns
+///
+/// - `(String, bool)`: The line without comments and whether anything was removed.
+///
+fn strip_line(line: &str, syntax: &CommentSyntax, state: &mut State) -> (String, bool) {
+    let mut result = String::with_capacity(line.len());
+    let mut had_comment = false;
+    let mut idx = 0;
+
+    while idx < line.len() {
+        let rest = &line[idx..];
+        let c = rest.chars().next().unwrap();
+        match state {
+            State::BlockComment => {
+                had_comment = true;
+                let (_, end) = syntax.block.unwrap();
+                match rest.find(end) {
+                    Some(pos) => {
+                        idx += pos + end.len();
+                        *state = State::Code;
+                    }
+                    None => idx = line.len(),
+                }
+                continue;
+            }
+            State::String { close, escapes, .. } => {
+                if *escapes && c == '\\' {
+                    // Keep the escaped character as is.
+                    let escaped_len = rest[1..].chars().next().map_or(0, char::len_utf8);
+                    result.push_str(&rest[..1 + escaped_len]);
+                    idx += 1 + escaped_len;
+                } else if rest.starts_with(close.as_str()) {
+                    result.push_str(close);
+                    idx += close.len();
+                    *state = State::Code;
+                } else {
+                    result.push(c);
+                    idx += c.len_utf8();
+                }
+                continue;
+            }
+            State::Code => {}
+        }
+
+        if let Some((len, string)) = string_start(line, idx, syntax) {
+            result.push_str(&rest[..len]);
+            idx += len;
+            *state = string;
+            continue;
+        }
+        if syntax.rust_literals && c == '\'' {
+            // A char literal is kept as is, otherwise the quote starts a lifetime.
+            let len = char_literal_len(rest).unwrap_or(1);
+            result.push_str(&rest[..len]);
+            idx += len;
+            continue;
+        }
+        if syntax.regex_literals && c == '/' && starts_regex(&result, rest) {
+            match regex_literal_len(rest) {
+                Some(len) => {
+                    result.push_str(&rest[..len]);
+                    idx += len;
+                    continue;
+                }
+                None => return (line.to_owned(), false),
+            }
+        }
+        if let Some((start, _)) = syntax.block {
+            if rest.starts_with(start) {
+                had_comment = true;
+                idx += start.len();
+                *state = State::BlockComment;
+                continue;
+            }
+        }
+        if syntax.line.iter().any(|marker| rest.starts_with(marker))
+            && is_comment_start(line, idx, syntax)
+        {
+            had_comment = true;
+            break;
+        }
+
+        result.push(c);
+        idx += c.len_utf8();
+    }
+
+    // A string that can't span lines and isn't continued with a trailing backslash means the
+    // quote was misread, such as an apostrophe in an unquoted value.
+    if let State::String {
+        spans_lines: false,
+        escapes,
+        ..
+    } = state
+    {
+        if !(*escapes && line.ends_with('\\')) {
+            *state = State::Code;
+            return (line.to_owned(), false);
+        }
+    }
+
+    (result, had_comment)
+}
+
+/// Checks whether a string starts at a position of a line.
+///
+/// ### Arguments
+///
+/// - `line`: The line.
+/// - `idx`: The byte position in the line.
+/// - `syntax`: The comment syntax of the language.
+///
+/// ### Returns
+///
+/// - `Option<(usize, State)>`: The length of the opening delimiter and the string state, or None
+///   if no string starts there.
+///
+fn string_start(line: &str, idx: usize, syntax: &CommentSyntax) -> Option<(usize, State)> {
+    let rest = &line[idx..];
+    let string = |open: &str, close: &str, escapes: bool, spans_lines: bool| {
+        Some((
+            open.len(),
+            State::String {
+                close: close.to_owned(),
+                escapes,
+                spans_lines,
+            },
+        ))
+    };
+
+    if syntax.rust_literals {
+        // Raw strings such as r"...", r#"..."# and br#"..."#, not preceded by an identifier.
+        let follows_identifier = line[..idx]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_alphanumeric() || c == '_');
+        let after_prefix = rest
+            .strip_prefix("br")
+            .or_else(|| rest.strip_prefix("cr"))
+            .or_else(|| rest.strip_prefix('r'));
+        if let (false, Some(after_prefix)) = (follows_identifier, after_prefix) {
+            let hashes = after_prefix.len() - after_prefix.trim_start_matches('#').len();
+            if after_prefix[hashes..].starts_with('"') {
+                let open_len = rest.len() - after_prefix.len() + hashes + 1;
+                let close = format!("\"{}", "#".repeat(hashes));
+                return string(&rest[..open_len], &close, false, true);
+            }
+        }
+    }
+    if let Some(delimiter) = syntax
+        .multiline_quotes
+        .iter()
+        .find(|delimiter| rest.starts_with(**delimiter))
+    {
+        return string(delimiter, delimiter, true, true);
+    }
+    if let Some(delimiter) = syntax
+        .raw_quotes
+        .iter()
+        .find(|delimiter| rest.starts_with(**delimiter))
+    {
+        return string(delimiter, delimiter, false, true);
+    }
+    let c = rest.chars().next()?;
+    if syntax.quotes.contains(&c) {
+        let quote = c.to_string();
+        return string(&quote, &quote, true, syntax.quotes_span_lines);
+    }
+    None
+}
+
+/// Measures a Rust char literal, such as `'a'`, `'"'` or `'\u{1F600}'`.
+///
+/// ### Arguments
+///
+/// - `rest`: The rest of the line, starting at the opening quote.
+///
+/// ### Returns
+///
+/// - `Option<usize>`: The length of the char literal, or None if the quote starts a lifetime.
+///
+fn char_literal_len(rest: &str) -> Option<usize> {
+    let mut chars = rest.char_indices().skip(1);
+    match chars.next()? {
+        (_, '\\') => chars
+            .skip(1)
+            .take(10)
+            .find(|(_, c)| *c == '\'')
+            .map(|(pos, _)| pos + 1),
+        (_, '\'') => None,
+        _ => chars
+            .next()
+            .filter(|(_, c)| *c == '\'')
+            .map(|(pos, _)| pos + 1),
+    }
+}
+
+/// Whether a `/` starts a regular expression literal rather than a division, from the code
+/// before it on the line.
+fn starts_regex(before: &str, rest: &str) -> bool {
+    if rest.starts_with("//") || rest.starts_with("/*") {
+        return false;
+    }
+    let before = before.trim_end();
+    match before.chars().next_back() {
+        None => true,
+        Some(previous) if "(,=:[!&|?{};+-*%<>~^".contains(previous) => true,
+        Some(_) => [
+            "return", "typeof", "case", "do", "else", "in", "of", "yield", "await",
+        ]
+        .iter()
+        .any(|keyword| {
+            before.strip_suffix(keyword).is_some_and(|start| {
+                !start.ends_with(|c: char| c.is_alphanumeric() || c == '_' || c == '$')
+            })
+        }),
+    }
+}
+
+/// Measures a regular expression literal, up to the closing `/`.
+///
+/// ### Arguments
+///
+/// - `rest`: The rest of the line, starting at the opening `/`.
+///
+/// ### Returns
+///
+/// - `Option<usize>`: The length of the literal without its flags, or None if it isn't closed on
+///   the line.
+///
+fn regex_literal_len(rest: &str) -> Option<usize> {
+    let mut in_class = false;
+    let mut chars = rest.char_indices().skip(1);
+    while let Some((pos, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '[' => in_class = true,
+            ']' => in_class = false,
+            '/' if !in_class => return Some(pos + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Whether a line comment marker starts a comment. A `#` only starts a comment at the start of
+/// the line or after whitespace, so shell variables such as `$#` are kept.
+fn is_comment_start(line: &str, idx: usize, syntax: &CommentSyntax) -> bool {
+    if syntax.line != ["#"] {
+        return true;
+    }
+    line[..idx]
+        .chars()
+        .next_back()
+        .is_none_or(char::is_whitespace)
+}
+
+/// Removes the trailing whitespace and the runs of blank lines.
+///
+/// Runs of blank lines are collapsed into a single blank line and the blank lines at the start
+/// and end of the file are removed.
+///
+/// ### Arguments
+///
+/// - `lines`: The file lines.
+///
+/// ### Returns
+///
+/// - `Vec<SourceLine>`: The compacted lines.
+///
+pub fn compact_lines(lines: Vec<SourceLine>) -> Vec<SourceLine> {
+    let mut compacted: Vec<SourceLine> = Vec::with_capacity(lines.len());
+    for line in lines {
+        let trimmed = line.text.trim_end();
+        if trimmed.is_empty()
+            && compacted
+                .last()
+                .is_none_or(|previous| previous.text.is_empty())
+        {
+            continue;
+        }
+        let text = if trimmed.len() == line.text.len() {
+            line.text
+        } else {
+            Cow::Owned(trimmed.to_owned())
+        };
+        compacted.push(SourceLine {
+            number: line.number,
+            text,
+        });
+    }
+    if compacted.last().is_some_and(|line| line.text.is_empty()) {
+        compacted.pop();
+    }
+    compacted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Strips the comments of a file and joins the lines back.
+    fn strip(content: &str, extension: &str) -> String {
+        strip_comments(SourceLine::numbered(content), extension)
+            .iter()
+            .map(|line| format!("{}\n", line.text))
+            .collect()
+    }
+
+    /// Strips the comments of each line in turn and returns the final lexer state.
+    fn state_after(lines: &[&str], syntax: &CommentSyntax) -> State {
+        let mut state = State::Code;
+        for line in lines {
+            strip_line(line, syntax, &mut state);
+        }
+        state
+    }
+
+    #[test]
+    fn strips_line_and_block_comments() {
+        let content =
+            "// header\nfn main() { // entry\n    /* a\n    b */\n    run(); /* c */\n}\n";
+        assert_eq!(strip(content, "rs"), "fn main() {\n    run();\n}\n");
+    }
+
+    #[test]
+    fn keeps_the_line_numbers() {
+        let lines = strip_comments(SourceLine::numbered("// a\nx = 1 // b\ny = 2\n"), "js");
+        let numbers: Vec<_> = lines.iter().map(|line| line.number).collect();
+        assert_eq!(numbers, vec![Some(2), Some(3)]);
+    }
+
+    #[test]
+    fn keeps_comment_markers_in_strings() {
+        let content = "let url = \"http://example.com\"; // c\nlet s = \"/* no */\";\n";
+        assert_eq!(
+            strip(content, "rs"),
+            "let url = \"http://example.com\";\nlet s = \"/* no */\";\n"
+        );
+        assert_eq!(strip("x = 'a # b' # c\n", "py"), "x = 'a # b'\n");
+    }
+
+    #[test]
+    fn carries_rust_strings_across_lines() {
+        let content = "let s = \"first\n// second\nthird\"; // c\n";
+        assert_eq!(
+            strip(content, "rs"),
+            "let s = \"first\n// second\nthird\";\n"
+        );
+    }
+
+    #[test]
+    fn keeps_rust_raw_strings() {
+        let content = "let a = r#\"say \"// hi\"\"#; // c\nlet b = r\"\n// inside\n\";\n";
+        assert_eq!(
+            strip(content, "rs"),
+            "let a = r#\"say \"// hi\"\"#;\nlet b = r\"\n// inside\n\";\n"
+        );
+        assert_eq!(
+            state_after(&["let c = br##\"open"], &RUST),
+            State::String {
+                close: "\"##".to_owned(),
+                escapes: false,
+                spans_lines: true
+            }
+        );
+    }
+
+    #[test]
+    fn tells_rust_char_literals_from_lifetimes() {
+        let content = "let q = '\"'; // c\nfn f<'a>(s: &'a str) -> &'a str { s } // c\n";
+        assert_eq!(
+            strip(content, "rs"),
+            "let q = '\"';\nfn f<'a>(s: &'a str) -> &'a str { s }\n"
+        );
+        assert_eq!(strip("let e = '\\''; // c\n", "rs"), "let e = '\\'';\n");
+    }
+
+    #[test]
+    fn keeps_go_raw_strings() {
+        let content = "var s = `raw\n// inside\n` // c\n";
+        assert_eq!(strip(content, "go"), "var s = `raw\n// inside\n`\n");
+    }
+
+    #[test]
+    fn keeps_python_triple_quoted_strings() {
+        let content = "s = \"\"\"\n# inside\n\"\"\" # c\n";
+        assert_eq!(strip(content, "py"), "s = \"\"\"\n# inside\n\"\"\"\n");
+    }
+
+    #[test]
+    fn keeps_javascript_regex_literals() {
+        let content = "const re = /\\/\\//g; // c\nx = y.match(/[/]/); // c\nd = a / b; // c\n";
+        assert_eq!(
+            strip(content, "js"),
+            "const re = /\\/\\//g;\nx = y.match(/[/]/);\nd = a / b;\n"
+        );
+        assert_eq!(strip("return /a\\/b/; // c\n", "ts"), "return /a\\/b/;\n");
+    }
+
+    #[test]
+    fn keeps_ambiguous_lines_unchanged() {
+        // The apostrophe isn't a quote, so the comment is kept rather than misread.
+        let content = "key: it's here # note\nother: \"a # b\" # c\n";
+        assert_eq!(
+            strip(content, "yaml"),
+            "key: it's here # note\nother: \"a # b\"\n"
+        );
+        // A regex literal that isn't closed on the line.
+        assert_eq!(strip("x = (/a // b\n", "js"), "x = (/a // b\n");
+    }
+
+    #[test]
+    fn continues_strings_after_a_trailing_backslash() {
+        assert_eq!(
+            state_after(&["char *s = \"a \\"], &C_STYLE),
+            State::String {
+                close: "\"".to_owned(),
+                escapes: true,
+                spans_lines: false
+            }
+        );
+        assert_eq!(
+            state_after(&["char *s = \"a", "b // c"], &C_STYLE),
+            State::Code
+        );
+    }
+
+    #[test]
+    fn keeps_the_shebang_and_shell_variables() {
+        let content = "#!/bin/sh\necho $# # count\n";
+        assert_eq!(strip(content, "sh"), "#!/bin/sh\necho $#\n");
+    }
+
+    #[test]
+    fn leaves_unknown_extensions_unchanged() {
+        assert_eq!(strip("// a\n# b\n", "txt"), "// a\n# b\n");
+    }
+
+    #[test]
+    fn collapses_blank_lines() {
+        let lines = compact_lines(SourceLine::numbered("\n\na  \n\n\n\nb\n\n"));
+        let text: Vec<_> = lines.iter().map(|line| line.text.as_ref()).collect();
+        assert_eq!(text, vec!["a", "", "b"]);
+    }
+}
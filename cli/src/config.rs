@@ -0,0 +1,539 @@
+//! # Config Module
+//!
+//! Handles loading the optional configuration files. The global configuration is read from
+//! `~/.codeprompt.toml` (the same file the TUI reads) and the project configuration is the
+//! nearest `.codeprompt.toml` found walking up from the project path. Both files use the
+//! `[defaults]` table, the project configuration takes precedence over the global configuration
+//! and the options passed on the command line take precedence over both.
+//!
+//! Named profiles are defined in `[profiles.<name>]` tables with the same keys as the
+//! `[defaults]` table. A profile selected with `--profile` is applied on top of the defaults.
+
+use crate::clipboard::ClipboardBackend;
+use crate::files::{parse_file_size, FilePatterns};
+use crate::template::OutputFormat;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Name of the configuration file.
+pub const CONFIG_FILE_NAME: &str = ".codeprompt.toml";
+
+/// Configuration loaded from the `.codeprompt.toml` files.
+///
+/// Unknown keys are rejected so that a misspelled option doesn't go unnoticed.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Default option values.
+    pub defaults: Defaults,
+    /// Named profiles bundling option values.
+    pub profiles: BTreeMap<String, Defaults>,
+    /// Command run by the TUI, which shares the global configuration file.
+    pub command: Option<String>,
+    /// Template directory of the TUI.
+    pub template_dir: Option<PathBuf>,
+}
+
+/// Default option values. Unset values fall back on the command line defaults.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Defaults {
+    /// Glob patterns to include.
+    pub include: Option<Vec<String>>,
+    /// Glob patterns to exclude.
+    pub exclude: Option<Vec<String>>,
+    /// Glob patterns of the files to reduce to a structural outline.
+    pub outline: Option<Vec<String>>,
+    /// Glob patterns of the files to keep the full code for in outline mode.
+    pub full_code: Option<Vec<String>>,
+    /// Whether to remove the comments from the file contents.
+    pub strip_comments: Option<bool>,
+    /// Whether to remove the comments, trailing whitespace and runs of blank lines.
+    pub compact: Option<bool>,
+    /// Whether to prioritize the exclude patterns in case of conflict.
+    pub exclude_priority: Option<bool>,
+    /// Whether to exclude files/folders from the source tree based on the exclude patterns.
+    pub exclude_from_tree: Option<bool>,
+    /// Whether to respect the .gitignore file.
+    pub gitignore: Option<bool>,
+    /// Whether to capture the git diff for staged changes.
+    pub diff_staged: Option<bool>,
+    /// Whether to capture the git diff for unstaged changes.
+    pub diff_unstaged: Option<bool>,
+    /// Revision to capture the git diff against.
+    pub diff_base: Option<String>,
+    /// Revision range to capture the git diff for.
+    pub diff_range: Option<String>,
+    /// Whether to only include the files touched by the git diff.
+    pub changed_only: Option<bool>,
+    /// Whether to skip the token count.
+    pub no_tokens: Option<bool>,
+    /// Tokenizer encoding or model name.
+    pub encoding: Option<String>,
+    /// Maximum number of tokens for the prompt.
+    pub max_tokens: Option<usize>,
+    /// Number of tokens above which the prompt is split into chunk files.
+    pub split: Option<usize>,
+    /// Size in bytes above which files are skipped, as a number of bytes or a string with a K,
+    /// M or G suffix.
+    #[serde(deserialize_with = "deserialize_file_size")]
+    pub max_file_size: Option<u64>,
+    /// Whether to keep the lockfiles, minified files and generated files.
+    pub include_generated: Option<bool>,
+    /// Whether to annotate the source tree with token counts.
+    pub tree_stats: Option<bool>,
+    /// Whether to turn off line numbers.
+    pub no_line_numbers: Option<bool>,
+    /// Whether to disable wrapping code inside markdown code blocks.
+    pub no_codeblock: Option<bool>,
+    /// Whether to render absolute paths instead of relative paths.
+    pub absolute_paths: Option<bool>,
+    /// Whether to disable copying to the clipboard.
+    pub no_clipboard: Option<bool>,
+    /// Clipboard backend to use.
+    pub clipboard_backend: Option<ClipboardBackend>,
+    /// Path to a template file or name of a built-in template.
+    pub template: Option<PathBuf>,
+    /// Format of the generated prompt.
+    pub format: Option<OutputFormat>,
+    /// Path to a template variables file.
+    pub vars_file: Option<PathBuf>,
+    /// User-defined template variables.
+    pub vars: Map<String, Value>,
+    /// Whether to skip rendering the spinner.
+    pub no_spinner: Option<bool>,
+    /// Whether to redact the possible secrets in the file contents.
+    pub redact: Option<bool>,
+    /// Whether to ignore all warnings.
+    pub no_warnings: Option<bool>,
+    /// Whether to never prompt for confirmation.
+    pub non_interactive: Option<bool>,
+    /// Whether to fail when sensitive files or unredacted secrets are found.
+    pub fail_on_sensitive: Option<bool>,
+    /// Whether to fail when any warning is reported.
+    pub fail_on_warnings: Option<bool>,
+    /// Names or glob patterns of the files and directories to always skip, replacing the
+    /// built-in ignore list.
+    pub ignore_list: Option<Vec<String>>,
+    /// Names or glob patterns added to the ignore list.
+    pub extra_ignore_list: Vec<String>,
+    /// File name glob patterns of the sensitive files, replacing the built-in patterns.
+    pub sensitive_files: Option<Vec<String>>,
+    /// File name glob patterns added to the sensitive file patterns.
+    pub extra_sensitive_files: Vec<String>,
+    /// Glob patterns, relative to the project root, of the vetted sensitive files.
+    pub sensitive_allowlist: Vec<String>,
+    /// Token count above which a warning is printed.
+    pub token_warning_threshold: Option<usize>,
+    /// Token count above which copying to the clipboard has to be confirmed.
+    pub clipboard_token_threshold: Option<usize>,
+}
+
+impl Defaults {
+    /// Merges two sets of defaults, the values of `other` take precedence.
+    ///
+    /// ### Arguments
+    ///
+    /// - `other`: The defaults to merge on top.
+    ///
+    /// ### Returns
+    ///
+    /// - `Defaults`: The merged defaults.
+    ///
+    fn merge(self, other: Defaults) -> Self {
+        let mut vars = self.vars;
+        vars.extend(other.vars);
+        let mut extra_ignore_list = self.extra_ignore_list;
+        extra_ignore_list.extend(other.extra_ignore_list);
+        let mut extra_sensitive_files = self.extra_sensitive_files;
+        extra_sensitive_files.extend(other.extra_sensitive_files);
+        let mut sensitive_allowlist = self.sensitive_allowlist;
+        sensitive_allowlist.extend(other.sensitive_allowlist);
+
+        Self {
+            include: other.include.or(self.include),
+            exclude: other.exclude.or(self.exclude),
+            outline: other.outline.or(self.outline),
+            full_code: other.full_code.or(self.full_code),
+            strip_comments: other.strip_comments.or(self.strip_comments),
+            compact: other.compact.or(self.compact),
+            exclude_priority: other.exclude_priority.or(self.exclude_priority),
+            exclude_from_tree: other.exclude_from_tree.or(self.exclude_from_tree),
+            gitignore: other.gitignore.or(self.gitignore),
+            diff_staged: other.diff_staged.or(self.diff_staged),
+            diff_unstaged: other.diff_unstaged.or(self.diff_unstaged),
+            diff_base: other.diff_base.or(self.diff_base),
+            diff_range: other.diff_range.or(self.diff_range),
+            changed_only: other.changed_only.or(self.changed_only),
+            no_tokens: other.no_tokens.or(self.no_tokens),
+            encoding: other.encoding.or(self.encoding),
+            max_tokens: other.max_tokens.or(self.max_tokens),
+            split: other.split.or(self.split),
+            max_file_size: other.max_file_size.or(self.max_file_size),
+            include_generated: other.include_generated.or(self.include_generated),
+            tree_stats: other.tree_stats.or(self.tree_stats),
+            no_line_numbers: other.no_line_numbers.or(self.no_line_numbers),
+            no_codeblock: other.no_codeblock.or(self.no_codeblock),
+            absolute_paths: other.absolute_paths.or(self.absolute_paths),
+            no_clipboard: other.no_clipboard.or(self.no_clipboard),
+            clipboard_backend: other.clipboard_backend.or(self.clipboard_backend),
+            template: other.template.or(self.template),
+            format: other.format.or(self.format),
+            vars_file: other.vars_file.or(self.vars_file),
+            vars,
+            no_spinner: other.no_spinner.or(self.no_spinner),
+            redact: other.redact.or(self.redact),
+            no_warnings: other.no_warnings.or(self.no_warnings),
+            non_interactive: other.non_interactive.or(self.non_interactive),
+            fail_on_sensitive: other.fail_on_sensitive.or(self.fail_on_sensitive),
+            fail_on_warnings: other.fail_on_warnings.or(self.fail_on_warnings),
+            ignore_list: other.ignore_list.or(self.ignore_list),
+            extra_ignore_list,
+            sensitive_files: other.sensitive_files.or(self.sensitive_files),
+            extra_sensitive_files,
+            sensitive_allowlist,
+            token_warning_threshold: other
+                .token_warning_threshold
+                .or(self.token_warning_threshold),
+            clipboard_token_threshold: other
+                .clipboard_token_threshold
+                .or(self.clipboard_token_threshold),
+        }
+    }
+
+    /// Builds the ignore list and sensitive file patterns from the built-in lists and the
+    /// configured overrides.
+    ///
+    /// ### Returns
+    ///
+    /// - `FilePatterns`: The file patterns.
+    ///
+    pub fn file_patterns(&self) -> FilePatterns {
+        let builtin = FilePatterns::default();
+        let mut ignore = self.ignore_list.clone().unwrap_or(builtin.ignore);
+        ignore.extend(self.extra_ignore_list.iter().cloned());
+        let mut sensitive = self.sensitive_files.clone().unwrap_or(builtin.sensitive);
+        sensitive.extend(self.extra_sensitive_files.iter().cloned());
+
+        FilePatterns {
+            ignore,
+            sensitive,
+            sensitive_allowlist: self.sensitive_allowlist.clone(),
+        }
+    }
+
+    /// Resolves the relative file paths against the directory of the configuration file.
+    ///
+    /// ### Arguments
+    ///
+    /// - `dir`: The directory containing the configuration file.
+    ///
+    fn resolve_paths(&mut self, dir: &Path) {
+        // The template can be the name of a built-in template, so it is only resolved if the
+        // file exists.
+        if let Some(template) = &self.template {
+            if template.is_relative() && dir.join(template).is_file() {
+                self.template = Some(dir.join(template));
+            }
+        }
+        if let Some(vars_file) = &self.vars_file {
+            if vars_file.is_relative() {
+                self.vars_file = Some(dir.join(vars_file));
+            }
+        }
+    }
+}
+
+impl Config {
+    /// Loads and merges the global and project configuration files.
+    ///
+    /// ### Arguments
+    ///
+    /// - `project_root`: The project path to start the project configuration search from.
+    ///
+    /// ### Returns
+    ///
+    /// - `Result<Config>`: The merged configuration, or the default configuration if no
+    ///   configuration file exists.
+    ///
+    pub fn load(project_root: &Path) -> Result<Self> {
+        let global_path = global_config_path();
+        // Walking up from a project under the home directory can end on the global file.
+        let canonical_global = global_path
+            .as_ref()
+            .and_then(|path| path.canonicalize().ok());
+        let project_path = find_project_config(project_root)
+            .filter(|path| Some(path) != canonical_global.as_ref());
+
+        let mut config = Self::default();
+        for path in [global_path, project_path].into_iter().flatten() {
+            if path.is_file() {
+                config = config.merge(Self::from_file(&path)?);
+            }
+        }
+        Ok(config)
+    }
+
+    /// Merges two configurations, the values of `other` take precedence. Profiles with the same
+    /// name are merged value by value.
+    ///
+    /// ### Arguments
+    ///
+    /// - `other`: The configuration to merge on top.
+    ///
+    /// ### Returns
+    ///
+    /// - `Config`: The merged configuration.
+    ///
+    fn merge(mut self, other: Config) -> Self {
+        for (name, profile) in other.profiles {
+            let merged = match self.profiles.remove(&name) {
+                Some(existing) => existing.merge(profile),
+                None => profile,
+            };
+            self.profiles.insert(name, merged);
+        }
+
+        Self {
+            defaults: self.defaults.merge(other.defaults),
+            profiles: self.profiles,
+            command: other.command.or(self.command),
+            template_dir: other.template_dir.or(self.template_dir),
+        }
+    }
+
+    /// Returns the defaults with the selected profile applied on top.
+    ///
+    /// ### Arguments
+    ///
+    /// - `profile`: The name of the profile to apply, if any.
+    ///
+    /// ### Returns
+    ///
+    /// - `Result<Defaults>`: The resolved defaults, or an error listing the available profiles
+    ///   if the profile doesn't exist.
+    ///
+    pub fn resolve(mut self, profile: Option<&str>) -> Result<Defaults> {
+        match profile {
+            Some(name) => {
+                let profile = self.profiles.remove(name).ok_or_else(|| {
+                    anyhow!(
+                        "Unknown profile '{}'. Available profiles: {}",
+                        name,
+                        if self.profiles.is_empty() {
+                            "none".to_owned()
+                        } else {
+                            self.profiles
+                                .keys()
+                                .map(String::as_str)
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        }
+                    )
+                })?;
+                Ok(self.defaults.merge(profile))
+            }
+            None => Ok(self.defaults),
+        }
+    }
+
+    /// Reads a single configuration file.
+    ///
+    /// ### Arguments
+    ///
+    /// - `path`: The path to the configuration file.
+    ///
+    /// ### Returns
+    ///
+    /// - `Result<Config>`: The configuration with its paths resolved against the file location.
+    ///
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+        let mut config: Self = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse config file: {}", path.display()))?;
+        if let Some(dir) = path.parent() {
+            config.defaults.resolve_paths(dir);
+            for profile in config.profiles.values_mut() {
+                profile.resolve_paths(dir);
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Deserializes a file size given as a number of bytes or a string with a size suffix.
+fn deserialize_file_size<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum FileSize {
+        Bytes(u64),
+        Text(String),
+    }
+
+    match Option::<FileSize>::deserialize(deserializer)? {
+        Some(FileSize::Bytes(size)) => Ok(Some(size)),
+        Some(FileSize::Text(size)) => parse_file_size(&size)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+/// Returns the path of the global configuration file.
+///
+/// ### Returns
+///
+/// - `Option<PathBuf>`: The path, if the home directory can be determined.
+///
+pub fn global_config_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(CONFIG_FILE_NAME))
+}
+
+/// Finds the nearest project configuration file by walking up from the project path.
+///
+/// ### Arguments
+///
+/// - `project_root`: The project path to start the search from.
+///
+/// ### Returns
+///
+/// - `Option<PathBuf>`: The path to the configuration file, if one was found.
+///
+pub fn find_project_config(project_root: &Path) -> Option<PathBuf> {
+    let start = project_root
+        .canonicalize()
+        .unwrap_or_else(|_| project_root.to_path_buf());
+    start
+        .ancestors()
+        .map(|dir| dir.join(CONFIG_FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> Config {
+        toml::from_str(content).unwrap()
+    }
+
+    #[test]
+    fn project_config_overrides_global_config() {
+        let global = parse(
+            r#"
+            [defaults]
+            max_tokens = 1000
+            encoding = "o200k"
+            extra_ignore_list = ["dist"]
+            vars = { team = "core", ticket = "A-1" }
+            "#,
+        );
+        let project = parse(
+            r#"
+            [defaults]
+            max_tokens = 2000
+            extra_ignore_list = ["build"]
+            vars = { ticket = "B-2" }
+            "#,
+        );
+
+        let defaults = Config::default()
+            .merge(global)
+            .merge(project)
+            .resolve(None)
+            .unwrap();
+
+        assert_eq!(defaults.max_tokens, Some(2000));
+        assert_eq!(defaults.encoding.as_deref(), Some("o200k"));
+        assert_eq!(defaults.extra_ignore_list, vec!["dist", "build"]);
+        assert_eq!(defaults.vars["team"], "core");
+        assert_eq!(defaults.vars["ticket"], "B-2");
+    }
+
+    #[test]
+    fn profile_overrides_defaults() {
+        let config = parse(
+            r#"
+            [defaults]
+            max_tokens = 1000
+            no_clipboard = true
+
+            [profiles.review]
+            max_tokens = 5000
+            template = "git_commit"
+            "#,
+        );
+
+        let defaults = config.resolve(Some("review")).unwrap();
+
+        assert_eq!(defaults.max_tokens, Some(5000));
+        assert_eq!(defaults.no_clipboard, Some(true));
+        assert_eq!(defaults.template, Some(PathBuf::from("git_commit")));
+    }
+
+    #[test]
+    fn profiles_with_the_same_name_are_merged() {
+        let global = parse(
+            r#"
+            [profiles.review]
+            max_tokens = 1000
+            compact = true
+            "#,
+        );
+        let project = parse(
+            r#"
+            [profiles.review]
+            max_tokens = 2000
+            "#,
+        );
+
+        let defaults = global.merge(project).resolve(Some("review")).unwrap();
+
+        assert_eq!(defaults.max_tokens, Some(2000));
+        assert_eq!(defaults.compact, Some(true));
+    }
+
+    #[test]
+    fn unknown_profile_lists_the_available_profiles() {
+        let config = parse("[profiles.review]\n[profiles.docs]\n");
+
+        let error = config.resolve(Some("reviw")).unwrap_err().to_string();
+
+        assert_eq!(
+            error,
+            "Unknown profile 'reviw'. Available profiles: docs, review"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert!(toml::from_str::<Config>("[defaults]\nmax_token = 10\n").is_err());
+        assert!(toml::from_str::<Config>("[profiles.review]\nmax_token = 10\n").is_err());
+        assert!(toml::from_str::<Config>("[default]\n").is_err());
+        // The TUI keys of the shared global file are accepted.
+        assert!(toml::from_str::<Config>("command = \"codeprompt\"\n").is_ok());
+    }
+
+    #[test]
+    fn parses_file_sizes_with_a_suffix() {
+        assert_eq!(
+            parse("[defaults]\nmax_file_size = \"2K\"\n")
+                .defaults
+                .max_file_size,
+            Some(2048)
+        );
+        assert_eq!(
+            parse("[defaults]\nmax_file_size = 512\n")
+                .defaults
+                .max_file_size,
+            Some(512)
+        );
+    }
+}
@@ -2,21 +2,103 @@
 //!
 //! Module that handles all file and file pathing functionality.
 
+use crate::compact::{compact_lines, strip_comments};
+use crate::outline::{outline, ELISION_MARKER};
+use crate::secrets::{scan_secrets, SecretFinding};
+use crate::symbols::{extract_symbols, SymbolQuery};
 use anyhow::{anyhow, Result};
 use colored::Colorize;
 use glob::Pattern;
-use ignore::WalkBuilder;
+use ignore::{WalkBuilder, WalkState};
+use regex::Regex;
+use serde::Serialize;
 use serde_json::json;
-use std::collections::HashSet;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::fs;
-use std::io::{self, Write};
-use std::path::Path;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
 use termtree::Tree;
+use tiktoken_rs::CoreBPE;
 use tracing::debug;
 
 const CODE_BLOCK_TICKS: &str = "```";
 
-const SENSITIVE_FILE_PATTERNS: &[&str] = &[
+/// Name of the ignore files, using the gitignore syntax, honored at every directory level.
+pub const CODEPROMPT_IGNORE_FILE: &str = ".codepromptignore";
+
+/// Number of bytes read from the start of a file to detect binary and generated files.
+const SNIFF_LENGTH: usize = 8192;
+
+/// Number of lines at the start of a file checked for a generated code header.
+const GENERATED_HEADER_LINES: usize = 5;
+
+/// Line length above which JavaScript and CSS files are considered minified.
+const MINIFIED_LINE_LENGTH: usize = 1000;
+
+/// Percentage of invalid UTF-8 bytes above which the start of a file is considered binary. Text
+/// in a legacy encoding such as Latin-1 stays below it.
+const BINARY_INVALID_UTF8_PERCENT: usize = 30;
+
+/// Magic numbers of common binary formats that don't necessarily contain NUL bytes early on.
+/// Signatures made of printable ASCII, such as `RIFF` or `%PDF-`, are left out since text files
+/// can start with them, and those formats are caught by the NUL byte and UTF-8 checks.
+const BINARY_MAGIC_NUMBERS: &[&[u8]] = &[
+    b"\x89PNG",
+    b"\xFF\xD8\xFF",
+    b"PK\x03\x04",
+    b"\x1F\x8B",
+    b"\x7FELF",
+    b"\xCA\xFE\xBA\xBE",
+    b"\xFE\xED\xFA",
+    b"\xCF\xFA\xED\xFE",
+    b"\xCE\xFA\xED\xFE",
+    b"7z\xBC\xAF\x27\x1C",
+    b"\xFD7zXZ",
+    b"Rar!\x1A\x07",
+];
+
+/// File names of the package manager lockfiles.
+const LOCKFILE_NAMES: &[&str] = &[
+    "Cargo.lock",
+    "package-lock.json",
+    "npm-shrinkwrap.json",
+    "yarn.lock",
+    "pnpm-lock.yaml",
+    "bun.lockb",
+    "poetry.lock",
+    "Pipfile.lock",
+    "uv.lock",
+    "Gemfile.lock",
+    "composer.lock",
+    "go.sum",
+    "flake.lock",
+    "mix.lock",
+    "Podfile.lock",
+    "packages.lock.json",
+];
+
+/// File name patterns of the minified files and source maps.
+const MINIFIED_FILE_PATTERNS: &[&str] = &[
+    "*.min.js",
+    "*.min.mjs",
+    "*.min.css",
+    "*.js.map",
+    "*.mjs.map",
+    "*.css.map",
+];
+
+/// Markers of a generated code header.
+const GENERATED_MARKERS: &[&str] = &["@generated", "<auto-generated"];
+
+/// The conventional `Code generated ... DO NOT EDIT.` header line, after the comment marker.
+static GENERATED_CODE_HEADER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\W*Code generated .*DO NOT EDIT\.").unwrap());
+
+/// The default file name patterns of the sensitive files.
+pub const SENSITIVE_FILE_PATTERNS: &[&str] = &[
     ".env",
     ".env.local",
     ".env.production",
@@ -33,7 +115,33 @@ const SENSITIVE_FILE_PATTERNS: &[&str] = &[
     "credentials.json",
 ];
 
-const IGNORE_LIST: &[&str] = &[".git", "node_modules", "venv"];
+/// The default names of the files and directories to always skip.
+pub const IGNORE_LIST: &[&str] = &[".git", "node_modules", "venv"];
+
+/// File name patterns for the files to always skip and the files to flag as sensitive.
+#[derive(Debug, Clone)]
+pub struct FilePatterns {
+    /// Names or glob patterns of the files and directories to always skip.
+    pub ignore: Vec<String>,
+    /// File name glob patterns of the sensitive files.
+    pub sensitive: Vec<String>,
+    /// Glob patterns, relative to the project root, of the sensitive files that have been vetted
+    /// and shouldn't be flagged.
+    pub sensitive_allowlist: Vec<String>,
+}
+
+impl Default for FilePatterns {
+    fn default() -> Self {
+        Self {
+            ignore: IGNORE_LIST.iter().map(|name| (*name).to_owned()).collect(),
+            sensitive: SENSITIVE_FILE_PATTERNS
+                .iter()
+                .map(|pattern| (*pattern).to_owned())
+                .collect(),
+            sensitive_allowlist: Vec::new(),
+        }
+    }
+}
 
 /// Parses a comma-delimited list from the user arguments.
 ///
@@ -54,151 +162,739 @@ pub fn parse_comma_delim_patterns(patterns: &Option<String>) -> Vec<String> {
     vec![]
 }
 
+/// An inclusive, 1-based range of lines.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LineRange {
+    /// The first line of the range.
+    pub start: usize,
+    /// The last line of the range.
+    pub end: usize,
+}
+
+/// An include pattern restricted to a range of lines, such as `src/main.rs:100-200`.
+#[derive(Debug, Clone)]
+pub struct RangedPattern {
+    /// The include pattern, without the line range.
+    pub pattern: String,
+    /// The lines of the matching files to include.
+    pub range: LineRange,
+}
+
+/// Splits the line ranges off the include patterns.
+///
+/// Patterns ending with `:<start>-<end>` or `:<line>` only include these lines of the matching
+/// files, the other patterns are returned as is.
+///
+/// ### Arguments
+///
+/// - `patterns`: The include patterns.
+///
+/// ### Returns
+///
+/// - `Result<(Vec<String>, Vec<RangedPattern>)>`: The include patterns without the line ranges,
+///   and the patterns that had a line range, or an error if a line range is invalid.
+///
+pub fn split_line_ranges(patterns: Vec<String>) -> Result<(Vec<String>, Vec<RangedPattern>)> {
+    let mut ranged = Vec::new();
+    let patterns = patterns
+        .into_iter()
+        .map(|pattern| {
+            let Some((path, range)) = pattern.rsplit_once(':') else {
+                return Ok(pattern);
+            };
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            let (Ok(start), Ok(end)) = (start.parse::<usize>(), end.parse::<usize>()) else {
+                return Ok(pattern);
+            };
+            if start == 0 || start > end {
+                return Err(anyhow!("Invalid line range in include pattern {}", pattern));
+            }
+            ranged.push(RangedPattern {
+                pattern: path.to_owned(),
+                range: LineRange { start, end },
+            });
+            Ok(path.to_owned())
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok((patterns, ranged))
+}
+
+/// Counts the tokens the JSON representation of a file takes up in the rendered prompt.
+pub struct FileCost<'a>(pub &'a (dyn Fn(&serde_json::Value) -> Result<usize> + Sync));
+
+impl fmt::Debug for FileCost<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FileCost")
+    }
+}
+
+/// Configuration options for the directory traversal.
+#[derive(Debug, Default)]
+pub struct TraversalConfig<'a> {
+    /// The include patterns.
+    pub include: &'a [String],
+    /// The exclude patterns.
+    pub exclude: &'a [String],
+    /// Whether to give priority to the exclude patterns.
+    pub exclude_priority: bool,
+    /// Whether to skip adding line numbers to the code sections.
+    pub no_line_numbers: bool,
+    /// Whether to use relative paths in the file tree.
+    pub relative_paths: bool,
+    /// Whether to exclude files picked up by the exclude patterns from the tree.
+    pub exclude_from_tree: bool,
+    /// Whether to skip wrapping the code in markdown code blocks.
+    pub no_codeblock: bool,
+    /// Whether or not to respect the gitignore file.
+    pub gitignore: bool,
+    /// Names or glob patterns of the files and directories to always skip.
+    pub ignore_list: &'a [String],
+    /// Absolute paths of the only files to include, on top of the include and exclude patterns.
+    pub include_paths: Option<&'a HashSet<PathBuf>>,
+    /// The tokenizer used to count the tokens of each file.
+    pub tokenizer: Option<&'a CoreBPE>,
+    /// Maximum number of tokens the source tree and the selected files can take up. Requires the
+    /// tokenizer to be set.
+    pub max_tokens: Option<usize>,
+    /// Counts the tokens a file takes up in the rendered prompt, including the template text
+    /// around it, for the token budget. Without it, only the path and the content are counted.
+    pub file_cost: Option<FileCost<'a>>,
+    /// Whether to annotate each source tree node with the token count of the files under it.
+    /// Requires the tokenizer to be set.
+    pub tree_stats: bool,
+    /// Whether to scan the file contents for secrets.
+    pub scan_secrets: bool,
+    /// Whether to replace the secrets found with redaction markers. Requires the secret scan to
+    /// be enabled.
+    pub redact: bool,
+    /// Size in bytes above which files are skipped.
+    pub max_file_size: Option<u64>,
+    /// Whether to keep the lockfiles, minified files and files with a generated code header.
+    pub include_generated: bool,
+    /// Glob patterns of the files to reduce to a structural outline, or None to keep the full
+    /// code of every file.
+    pub outline: Option<&'a [String]>,
+    /// Glob patterns of the files to keep the full code for when the outline mode is enabled.
+    pub full_code: &'a [String],
+    /// The include patterns restricted to a range of lines.
+    pub line_ranges: &'a [RangedPattern],
+    /// The symbols to extract, only the matching functions, types and impl blocks of the files
+    /// are kept when set.
+    pub symbols: &'a [SymbolQuery],
+    /// Whether to remove the comments from the file contents.
+    pub strip_comments: bool,
+    /// Whether to remove the trailing whitespace and the runs of blank lines.
+    pub compact: bool,
+}
+
+/// The result of a directory traversal.
+#[derive(Debug, Default)]
+pub struct Traversal {
+    /// The string representation of the source tree.
+    pub tree: String,
+    /// The JSON representation of the included files.
+    pub files: Vec<serde_json::Value>,
+    /// The paths of the files that were left out to stay within the token budget.
+    pub omitted: Vec<String>,
+    /// The possible secrets found in the included files.
+    pub secrets: Vec<SecretFinding>,
+    /// The files skipped because they are binary, too large or generated.
+    pub skipped: Vec<SkippedFile>,
+    /// The symbol queries that didn't match any symbol.
+    pub missing_symbols: Vec<String>,
+    /// The files left out because their line ranges are all past the end of the file.
+    pub out_of_range: Vec<String>,
+}
+
+/// The reason a file was skipped.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SkipReason {
+    /// The file content is binary.
+    Binary,
+    /// The file is larger than the maximum file size.
+    TooLarge {
+        /// The file size in bytes.
+        size: u64,
+        /// The maximum file size in bytes.
+        limit: u64,
+    },
+    /// The file is a package manager lockfile.
+    Lockfile,
+    /// The file is minified code or a source map.
+    Minified,
+    /// The file has a generated code header.
+    Generated,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Binary => write!(f, "binary"),
+            Self::TooLarge { size, limit } => write!(
+                f,
+                "too large, {} over the {} limit",
+                format_file_size(*size),
+                format_file_size(*limit)
+            ),
+            Self::Lockfile => write!(f, "lockfile"),
+            Self::Minified => write!(f, "minified"),
+            Self::Generated => write!(f, "generated"),
+        }
+    }
+}
+
+/// A file skipped during the traversal.
+#[derive(Debug, Clone)]
+pub struct SkippedFile {
+    /// The path of the file, as it would be displayed in the prompt.
+    pub path: String,
+    /// The reason the file was skipped.
+    pub reason: SkipReason,
+}
+
+/// An entry found by the parallel directory walk.
+#[derive(Debug)]
+struct WalkEntry {
+    /// The path relative to the root directory.
+    relative_path: PathBuf,
+    /// Whether the entry is added to the source tree.
+    in_tree: bool,
+    /// The processed file, if the entry is a file that passed the include and exclude patterns.
+    file: Option<FileOutcome>,
+}
+
+/// The result of processing a single file.
+#[derive(Debug)]
+enum FileOutcome {
+    /// The file was read and formatted.
+    Included {
+        /// The JSON representation of the file.
+        json: serde_json::Value,
+        /// The token count of the formatted file content.
+        token_count: Option<usize>,
+        /// The possible secrets found in the file.
+        secrets: Vec<SecretFinding>,
+        /// The indices of the symbol queries that matched a symbol in the file.
+        matched_symbols: Vec<usize>,
+    },
+    /// The file was skipped before it was read.
+    Skipped(SkippedFile),
+    /// The file was left out because its line ranges are all past the end of the file.
+    OutOfRange(String),
+}
+
+/// How the content of a file is reduced.
+#[derive(Debug)]
+enum ContentMode<'q> {
+    /// The full file content.
+    Full,
+    /// The structural outline of the file.
+    Outline,
+    /// The line ranges of the file.
+    Lines(Vec<LineRange>),
+    /// The matching symbols, with the symbol queries that apply to the file and their index.
+    Symbols(Vec<(usize, &'q SymbolQuery)>),
+}
+
 /// Starts at the directory root path and traverses the files to build a tree representation.
 ///
+/// The directory is walked in parallel and the files are read and formatted on the walker
+/// threads. The entries are then sorted by path so the source tree and files are in file name
+/// order and the output is deterministic. If a token budget is configured, files are added in
+/// that order as long as they fit in the budget left over after the source tree, and the files
+/// that don't fit are reported as omitted.
+///
 /// ### Arguments
 ///
 /// - `root`: The path to the root directory.
-/// - `include`: The include patterns.
-/// - `exclude`: The exclude patterns.
-/// - `exclude_priority`: Whether to give priority to the exclude patterns.
-/// - `no_line_numbers`: Whether to skip adding line numbers to the code sections.
-/// - `relative_paths`: Whether to use relative paths in the file tree.
-/// - `exclude_from_tree`: Whether to exclude files picked up by the exclude patterns from the
-/// tree.
-/// - `no_codeblock`: Whether to wrap the code in markdown code blocks.
-/// - `gitignore`: Whether or not to respect the gitignore file.
-/// - `verbose`: Whether to print the glob pattern matching for investigation.
+/// - `config`: The traversal configuration.
 ///
 /// ### Returns
 ///
-/// - `Result<(String, Vec<serde_json::Value>)>`: The string representation of the tree and the
-/// JSON representation.
+/// - `Result<Traversal>`: The string representation of the tree, the JSON representation of the
+///   files, and the files omitted because of the token budget.
 ///
-pub fn traverse_directory(
-    root: &Path,
-    include: &[String],
-    exclude: &[String],
-    exclude_priority: bool,
-    no_line_numbers: bool,
-    relative_paths: bool,
-    exclude_from_tree: bool,
-    no_codeblock: bool,
-    gitignore: bool,
-) -> Result<(String, Vec<serde_json::Value>)> {
+pub fn traverse_directory(root: &Path, config: &TraversalConfig) -> Result<Traversal> {
+    let TraversalConfig {
+        include,
+        exclude,
+        exclude_priority,
+        relative_paths,
+        exclude_from_tree,
+        gitignore,
+        ..
+    } = *config;
+
     debug!(
         include_patterns = ?include,
         exclude_patterns = ?exclude,
         "Starting directory traversal"
     );
 
-    // Will hold the files found in the traversal.
-    let mut files = Vec::new();
     // Canonicalize returns the canonical, absolute form of a path with all intermediate components
     // normalized and symbolic links resolved. It errors if the path does not exist or if the final
     // component in path is not a directory.
     let canonical_root_path = root.canonicalize()?;
     let parent_dir = basename(&canonical_root_path);
+    // The include and exclude patterns are matched against the paths relative to the current
+    // directory, which is only looked up once.
+    let current_dir = std::env::current_dir()?;
 
     // Compile glob patterns
     let include_patterns = compile_patterns(include)?;
     let exclude_patterns = compile_patterns(exclude)?;
+    let ignore_patterns = compile_patterns(config.ignore_list)?;
+    let outline_patterns = config.outline.map(compile_patterns).transpose()?;
+    let full_code_patterns = compile_patterns(config.full_code)?;
+    let line_range_patterns = config
+        .line_ranges
+        .iter()
+        .map(|ranged| {
+            let normalized = ranged.pattern.strip_prefix("./").unwrap_or(&ranged.pattern);
+            Pattern::new(normalized)
+                .map(|pattern| (pattern, ranged.range))
+                .map_err(|e| anyhow!("Invalid pattern {}: {}", ranged.pattern, e))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let symbol_path_patterns = config
+        .symbols
+        .iter()
+        .map(|query| {
+            query
+                .path
+                .as_deref()
+                .map(|p| {
+                    let normalized = p.strip_prefix("./").unwrap_or(p);
+                    Pattern::new(normalized).map_err(|e| anyhow!("Invalid pattern {}: {}", p, e))
+                })
+                .transpose()
+        })
+        .collect::<Result<Vec<_>>>()?;
 
-    let tree = WalkBuilder::new(&canonical_root_path)
+    let entries = Mutex::new(Vec::new());
+    WalkBuilder::new(&canonical_root_path)
         .standard_filters(false)
         .git_ignore(gitignore)
-        .filter_entry(|entry| !in_ignore_list(entry.path()))
-        .build()
-        // Filter out errors, only keep successful entries.
-        .filter_map(|e| e.ok())
-        // Use fold to accumulate entries into the tree structure, starting from the root
-        // directory.
-        .fold(Tree::new(parent_dir.to_owned()), |mut root, entry| {
-            let path = entry.path();
-            // Computes the relative path from the root directory.
-            if let Ok(relative_path) = path.strip_prefix(&canonical_root_path) {
-                // Initialize the current tree to the root of the tree.
-                let mut current_tree = &mut root;
-                // Iterate over each part of the relative path.
-                for component in relative_path.components() {
-                    let component_string = component.as_os_str().to_string_lossy().to_string();
-                    // Check if the file should be excluded from the tree based on the
-                    // exclude patterns and exclude_from_tree arguments. Break the path component
-                    // loop if it any part of the path should be excluded.
-                    if exclude_from_tree
-                        && !include_file(
-                            path,
-                            &include_patterns,
-                            &exclude_patterns,
-                            exclude_priority,
-                            relative_paths,
-                        )
-                    {
-                        break;
-                    }
-
-                    // Check if the current component already exists in the current tree.
-                    current_tree = if let Some(index) = current_tree
-                        .leaves
-                        .iter_mut()
-                        .position(|child| child.root == component_string)
-                    {
-                        // Update the current tree to point to the existing component.
-                        &mut current_tree.leaves[index]
-                    // Component doesn't already exist, create a new tree node and add to the
-                    // current tree.
-                    } else {
-                        let new_tree = Tree::new(component_string.clone());
-                        current_tree.leaves.push(new_tree);
-                        current_tree.leaves.last_mut().unwrap()
-                    };
-                }
-
-                if path.is_file()
+        .add_custom_ignore_filename(CODEPROMPT_IGNORE_FILE)
+        .filter_entry(move |entry| !in_ignore_list(entry.path(), &ignore_patterns))
+        .build_parallel()
+        .run(|| {
+            Box::new(|entry| {
+                // Skip the entries that can't be read.
+                let Ok(entry) = entry else {
+                    return WalkState::Continue;
+                };
+                let path = entry.path();
+                // Computes the relative path from the root directory.
+                let Ok(relative_path) = path.strip_prefix(&canonical_root_path) else {
+                    return WalkState::Continue;
+                };
+                let included = in_include_paths(path, config.include_paths)
                     && include_file(
                         path,
+                        &current_dir,
                         &include_patterns,
                         &exclude_patterns,
                         exclude_priority,
                         relative_paths,
-                    )
-                {
-                    // Read in the file contents into bytes.
-                    if let Ok(file_bytes) = fs::read(path) {
-                        let code_string = String::from_utf8_lossy(&file_bytes);
-                        // Get the formatted content block.
-                        let formatted_block = wrap_content(
-                            &code_string,
-                            path.extension().and_then(|ext| ext.to_str()).unwrap_or(""),
-                            no_line_numbers,
-                            no_codeblock,
-                        );
-
-                        if !formatted_block.trim().is_empty()
-                            && !formatted_block.contains(char::REPLACEMENT_CHARACTER)
-                        {
-                            // If the relative paths bool is True, get the relative path.
-                            let file_path = if relative_paths {
-                                format!("{}/{}", parent_dir, relative_path.display())
-                            // If the relative paths bool is False, get the full path.
-                            } else {
-                                path.display().to_string()
-                            };
-
-                            files.push(json!({
-                                "path": file_path,
-                                "extension": path.extension()
-                                    .and_then(|ext| ext.to_str())
-                                    .unwrap_or(""),
-                                "code": formatted_block
-                            }));
-                        }
-                    }
+                    );
+                let is_file = entry.file_type().is_some_and(|file_type| {
+                    file_type.is_file() || (file_type.is_symlink() && path.is_file())
+                });
+
+                let file = if is_file && included {
+                    // If the relative paths bool is True, get the relative path.
+                    let file_path = if relative_paths {
+                        format!("{}/{}", parent_dir, relative_path.display())
+                    // If the relative paths bool is False, get the full path.
+                    } else {
+                        path.display().to_string()
+                    };
+                    let line_ranges: Vec<LineRange> = line_range_patterns
+                        .iter()
+                        .filter(|(pattern, _)| {
+                            matches_patterns(path, &current_dir, [pattern], relative_paths)
+                        })
+                        .map(|(_, range)| *range)
+                        .collect();
+                    // Line ranges take precedence over the symbols and the outline.
+                    let mode = if !line_ranges.is_empty() {
+                        Some(ContentMode::Lines(line_ranges))
+                    } else if !config.symbols.is_empty() {
+                        // Only search the files matching the path of the symbol queries.
+                        let queries: Vec<_> = config
+                            .symbols
+                            .iter()
+                            .enumerate()
+                            .filter(|(idx, _)| {
+                                symbol_path_patterns[*idx].as_ref().is_none_or(|pattern| {
+                                    let relative_path =
+                                        path.strip_prefix(&current_dir).unwrap_or(path);
+                                    pattern.matches_path(relative_path)
+                                        || pattern.matches_path(path)
+                                })
+                            })
+                            .collect();
+                        (!queries.is_empty()).then_some(ContentMode::Symbols(queries))
+                    } else if outline_patterns.as_ref().is_some_and(|patterns| {
+                        matches_patterns(path, &current_dir, patterns, relative_paths)
+                            && !matches_patterns(
+                                path,
+                                &current_dir,
+                                &full_code_patterns,
+                                relative_paths,
+                            )
+                    }) {
+                        Some(ContentMode::Outline)
+                    } else {
+                        Some(ContentMode::Full)
+                    };
+                    mode.and_then(|mode| process_file(path, file_path, mode, config))
+                } else {
+                    None
+                };
+
+                entries.lock().unwrap().push(WalkEntry {
+                    relative_path: relative_path.to_path_buf(),
+                    // Exclude the entry from the tree based on the exclude patterns and
+                    // exclude_from_tree arguments.
+                    in_tree: !exclude_from_tree || included,
+                    file,
+                });
+                WalkState::Continue
+            })
+        });
+
+    // Sorting the paths component by component gives the same order as walking the directories
+    // sequentially in file name order.
+    let mut entries = entries.into_inner().unwrap();
+    entries.sort_unstable_by(|a, b| a.relative_path.cmp(&b.relative_path));
+
+    // Will hold the files found in the traversal.
+    let mut files = Vec::new();
+    // Will hold the token count of each file by relative path when the tree stats are enabled.
+    let mut path_tokens = HashMap::new();
+    // Will hold the possible secrets found in the files.
+    let mut secrets = Vec::new();
+    // Will hold the files skipped because they are binary, too large or generated.
+    let mut skipped = Vec::new();
+    // Will hold the files whose line ranges are all past the end of the file.
+    let mut out_of_range = Vec::new();
+    // Will hold whether each symbol query matched a symbol.
+    let mut symbols_found = vec![false; config.symbols.len()];
+    let budget = config.tokenizer.zip(config.max_tokens);
+    let mut tree = Tree::new(parent_dir.to_owned());
+
+    for entry in entries {
+        if entry.in_tree {
+            insert_tree_path(&mut tree, &entry.relative_path);
+        }
+        match entry.file {
+            Some(FileOutcome::Included {
+                json,
+                token_count,
+                secrets: file_secrets,
+                matched_symbols,
+            }) => {
+                for idx in matched_symbols {
+                    symbols_found[idx] = true;
                 }
+                if let (true, Some(token_count)) = (config.tree_stats, token_count) {
+                    path_tokens.insert(entry.relative_path, token_count);
+                }
+                secrets.extend(file_secrets);
+                files.push(json);
             }
-            root
-        });
-    Ok((tree.to_string(), files))
+            Some(FileOutcome::Skipped(file)) => skipped.push(file),
+            Some(FileOutcome::OutOfRange(path)) => out_of_range.push(path),
+            None => {}
+        }
+    }
+
+    if config.tree_stats {
+        annotate_tree_tokens(&mut tree, Path::new(""), &path_tokens);
+    }
+    let tree = tree.to_string();
+
+    let (files, omitted) = match budget {
+        Some((bpe, max_tokens)) => {
+            let tree_tokens = bpe.encode_with_special_tokens(&tree).len();
+            // Without a cost function, only the path and the content of a file are counted.
+            let file_cost = |file: &serde_json::Value| match &config.file_cost {
+                Some(cost) => (cost.0)(file),
+                None => Ok(file["token_count"].as_u64().unwrap_or(0) as usize
+                    + bpe
+                        .encode_with_special_tokens(file["path"].as_str().unwrap_or(""))
+                        .len()),
+            };
+            apply_token_budget(files, max_tokens.saturating_sub(tree_tokens), file_cost)?
+        }
+        None => (files, Vec::new()),
+    };
+    secrets.retain(|secret| !omitted.contains(&secret.path));
+    let missing_symbols = config
+        .symbols
+        .iter()
+        .zip(symbols_found)
+        .filter(|(_, found)| !found)
+        .map(|(query, _)| query.spec.clone())
+        .collect();
+
+    Ok(Traversal {
+        tree,
+        files,
+        omitted,
+        secrets,
+        skipped,
+        missing_symbols,
+        out_of_range,
+    })
+}
+
+/// Reads, scans and formats a file that passed the include and exclude patterns.
+///
+/// ### Arguments
+///
+/// - `path`: The path to the file.
+/// - `file_path`: The path of the file as displayed in the prompt.
+/// - `mode`: How the file content is reduced.
+/// - `config`: The traversal configuration.
+///
+/// ### Returns
+///
+/// - `Option<FileOutcome>`: The processed file, or None if the file couldn't be read, is empty
+///   or has none of the requested symbols.
+///
+fn process_file(
+    path: &Path,
+    file_path: String,
+    mode: ContentMode,
+    config: &TraversalConfig,
+) -> Option<FileOutcome> {
+    // Check for binary, oversized and generated files before reading the whole file.
+    if let Some(reason) = skip_reason(path, config.max_file_size, config.include_generated) {
+        debug!(path = file_path, reason = %reason, "Skipping file");
+        return Some(FileOutcome::Skipped(SkippedFile {
+            path: file_path,
+            reason,
+        }));
+    }
+
+    // Read in the file contents into bytes.
+    let file_bytes = fs::read(path).ok()?;
+    // Invalid UTF-8 sequences in text files are replaced rather than dropping the file.
+    let code_string = String::from_utf8_lossy(&file_bytes);
+    // Scan for secrets before the content is formatted.
+    let (code_content, secrets) = if config.scan_secrets {
+        scan_secrets(&code_string, &file_path, config.redact)
+    } else {
+        (code_string.clone(), Vec::new())
+    };
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+    let mut is_outline = false;
+    let mut line_ranges = Vec::new();
+    let mut symbols = Vec::new();
+    let mut matched_symbols = Vec::new();
+    let mut lines = match mode {
+        ContentMode::Full => SourceLine::numbered(&code_content),
+        // Files in a language without outline support keep their full code.
+        ContentMode::Outline => match outline(&code_content, extension) {
+            Some(lines) => {
+                is_outline = true;
+                lines
+            }
+            None => SourceLine::numbered(&code_content),
+        },
+        ContentMode::Lines(ranges) => {
+            line_ranges = merge_line_ranges(ranges, code_content.lines().count());
+            if line_ranges.is_empty() {
+                return Some(FileOutcome::OutOfRange(file_path));
+            }
+            let rows: Vec<(usize, usize)> = line_ranges
+                .iter()
+                .map(|range| (range.start - 1, range.end - 1))
+                .collect();
+            SourceLine::select(&code_content, &rows)
+        }
+        // Files without any of the requested symbols are left out.
+        ContentMode::Symbols(queries) => {
+            let extraction = extract_symbols(&code_content, extension, &queries)?;
+            symbols = extraction.symbols;
+            matched_symbols = extraction.matched;
+            extraction.lines
+        }
+    };
+    if config.strip_comments {
+        lines = strip_comments(lines, extension);
+    }
+    if config.compact {
+        lines = compact_lines(lines);
+    }
+    // Get the formatted content block.
+    let formatted_block = wrap_content(
+        &lines,
+        extension,
+        config.no_line_numbers,
+        config.no_codeblock,
+    );
+
+    if formatted_block.trim().is_empty() {
+        return None;
+    }
+
+    let token_count = config
+        .tokenizer
+        .map(|bpe| bpe.encode_with_special_tokens(&formatted_block).len());
+
+    let json = json!({
+        "path": file_path,
+        "extension": extension,
+        "code": formatted_block,
+        "outline": is_outline,
+        "line_ranges": line_ranges,
+        "symbols": symbols,
+        "token_count": token_count,
+        "line_count": code_string.lines().count(),
+        "byte_size": file_bytes.len(),
+    });
+
+    Some(FileOutcome::Included {
+        json,
+        token_count,
+        secrets,
+        matched_symbols,
+    })
+}
+
+/// Sorts and merges the line ranges of a file, clamped to the number of lines.
+///
+/// ### Arguments
+///
+/// - `ranges`: The line ranges.
+/// - `line_count`: The number of lines in the file.
+///
+/// ### Returns
+///
+/// - `Vec<LineRange>`: The sorted and merged line ranges, ranges past the end of the file are
+///   dropped.
+///
+fn merge_line_ranges(mut ranges: Vec<LineRange>, line_count: usize) -> Vec<LineRange> {
+    ranges.sort_unstable_by_key(|range| (range.start, range.end));
+    let mut merged: Vec<LineRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        if range.start > line_count {
+            continue;
+        }
+        let end = range.end.min(line_count);
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end + 1 => last.end = last.end.max(end),
+            _ => merged.push(LineRange {
+                start: range.start,
+                end,
+            }),
+        }
+    }
+    merged
+}
+
+/// Adds the components of a path to the source tree.
+///
+/// The paths are inserted in sorted order, so an existing node for a component is always the
+/// last leaf of its parent.
+///
+/// ### Arguments
+///
+/// - `root`: The root of the source tree.
+/// - `relative_path`: The path relative to the root directory.
+///
+fn insert_tree_path(root: &mut Tree<String>, relative_path: &Path) {
+    // Initialize the current tree to the root of the tree.
+    let mut current_tree = root;
+    // Iterate over each part of the relative path.
+    for component in relative_path.components() {
+        let component_string = component.as_os_str().to_string_lossy().to_string();
+        let exists = current_tree
+            .leaves
+            .last()
+            .is_some_and(|child| child.root == component_string);
+        // Component doesn't already exist, create a new tree node and add to the current tree.
+        if !exists {
+            current_tree.leaves.push(Tree::new(component_string));
+        }
+        current_tree = current_tree.leaves.last_mut().unwrap();
+    }
+}
+
+/// Appends the aggregated token count of the files under each node to the node label.
+///
+/// ### Arguments
+///
+/// - `node`: The tree node to annotate.
+/// - `path`: The path of the node relative to the root directory.
+/// - `path_tokens`: The token count of each file by relative path.
+///
+/// ### Returns
+///
+/// - `usize`: The aggregated token count of the node.
+///
+fn annotate_tree_tokens(
+    node: &mut Tree<String>,
+    path: &Path,
+    path_tokens: &HashMap<PathBuf, usize>,
+) -> usize {
+    let mut total = path_tokens.get(path).copied().unwrap_or(0);
+    for leaf in node.leaves.iter_mut() {
+        let leaf_path = path.join(&leaf.root);
+        total += annotate_tree_tokens(leaf, &leaf_path, path_tokens);
+    }
+    node.root = format!("{} ({} tokens)", node.root, total);
+    total
+}
+
+/// Selects the files that fit in the token budget.
+///
+/// Files are considered in order and a file is kept if it still fits in the remaining budget,
+/// so a large file does not prevent smaller files after it from being included.
+///
+/// ### Arguments
+///
+/// - `files`: The JSON representation of the files, in traversal order.
+/// - `budget`: The number of tokens available for the files.
+/// - `file_cost`: Counts the tokens a file takes up in the prompt.
+///
+/// ### Returns
+///
+/// - `Result<(Vec<serde_json::Value>, Vec<String>)>`: The kept files and the paths of the
+///   omitted files, or an error if the cost of a file couldn't be counted.
+///
+fn apply_token_budget(
+    files: Vec<serde_json::Value>,
+    budget: usize,
+    file_cost: impl Fn(&serde_json::Value) -> Result<usize>,
+) -> Result<(Vec<serde_json::Value>, Vec<String>)> {
+    let mut remaining = budget;
+    let mut kept = Vec::new();
+    let mut omitted = Vec::new();
+
+    for file in files {
+        // Once the budget is used up the remaining files are omitted without counting them.
+        let tokens = match remaining {
+            0 => None,
+            _ => Some(file_cost(&file)?),
+        };
+        match tokens {
+            Some(tokens) if tokens <= remaining => {
+                remaining -= tokens;
+                kept.push(file);
+            }
+            _ => {
+                debug!(path = ?file["path"], tokens = ?tokens, remaining = remaining, "Omitting file");
+                omitted.push(file["path"].as_str().unwrap_or_default().to_owned());
+            }
+        }
+    }
+
+    Ok((kept, omitted))
 }
 
 /// Scans for sensitive files without building the tree.
@@ -211,6 +907,7 @@ pub fn traverse_directory(
 /// - `exclude_priority`: Whether to give priority to the exclude patterns.
 /// - `relative_paths`: Whether to use relative paths.
 /// - `gitignore`: Whether to respect the gitignore file.
+/// - `file_patterns`: The ignore list, sensitive file patterns and sensitive file allowlist.
 ///
 /// ### Returns
 ///
@@ -223,16 +920,22 @@ pub fn check_sensitive_files(
     exclude_priority: bool,
     relative_paths: bool,
     gitignore: bool,
+    file_patterns: &FilePatterns,
 ) -> Result<Vec<String>> {
     let canonical_root_path = root.canonicalize()?;
     let include_patterns = compile_patterns(include)?;
     let exclude_patterns = compile_patterns(exclude)?;
+    let ignore_patterns = compile_patterns(&file_patterns.ignore)?;
+    let sensitive_patterns = compile_patterns(&file_patterns.sensitive)?;
+    let allowlist_patterns = compile_patterns(&file_patterns.sensitive_allowlist)?;
+    let current_dir = std::env::current_dir()?;
     let mut sensitive_files = Vec::new();
 
     let tree = WalkBuilder::new(&canonical_root_path)
         .standard_filters(false)
         .git_ignore(gitignore)
-        .filter_entry(|entry| !in_ignore_list(entry.path()))
+        .add_custom_ignore_filename(CODEPROMPT_IGNORE_FILE)
+        .filter_entry(move |entry| !in_ignore_list(entry.path(), &ignore_patterns))
         .build();
 
     for entry in tree.filter_map(|e| e.ok()) {
@@ -240,15 +943,17 @@ pub fn check_sensitive_files(
         if path.is_file()
             && include_file(
                 path,
+                &current_dir,
                 &include_patterns,
                 &exclude_patterns,
                 exclude_priority,
                 relative_paths,
             )
-            && is_sensitive_file(path)
+            && is_sensitive_file(path, &sensitive_patterns)
+            && !in_allowlist(path, &canonical_root_path, &allowlist_patterns)
         {
             let display_path = if relative_paths {
-                path.strip_prefix(std::env::current_dir().unwrap())
+                path.strip_prefix(&current_dir)
                     .unwrap_or(path)
                     .display()
                     .to_string()
@@ -307,11 +1012,12 @@ fn handle_special_case(p: &Path) -> String {
 ///
 /// ### Arguments
 ///
-/// - `path`: The path to the file to check.
+/// - `path`: The canonical path to the file to check.
+/// - `current_dir`: The current directory the relative paths are computed from.
 /// - `include_patterns`: The pre-compiled include patterns.
 /// - `exclude_patterns`: The pre-compiled exclude patterns.
 /// - `exclude_priority`: Whether to put precedence on the include or exclude patterns if they
-/// conflict.
+///   conflict.
 /// - `relative_paths`: Whether to use relative paths for each file included.
 ///
 /// ### Returns
@@ -320,23 +1026,17 @@ fn handle_special_case(p: &Path) -> String {
 ///
 fn include_file(
     path: &Path,
+    current_dir: &Path,
     include_patterns: &HashSet<Pattern>,
     exclude_patterns: &HashSet<Pattern>,
     exclude_priority: bool,
     relative_paths: bool,
 ) -> bool {
-    let canonical_root_path = match fs::canonicalize(path) {
-        Ok(path) => path,
-        Err(e) => {
-            eprintln!("Failed to canonicalize path: {}", e);
-            return false;
-        }
-    };
-    let path_string = canonical_root_path.to_str().unwrap();
-    let relative_path = path
-        .strip_prefix(std::env::current_dir().unwrap())
-        .unwrap_or(path);
-    let relative_path_string = relative_path.to_str().unwrap();
+    let path_string = path.to_string_lossy();
+    let path_string = path_string.as_ref();
+    let relative_path = path.strip_prefix(current_dir).unwrap_or(path);
+    let relative_path_string = relative_path.to_string_lossy();
+    let relative_path_string = relative_path_string.as_ref();
 
     debug!("----------------------------------------------------------------");
     debug!(
@@ -348,40 +1048,12 @@ fn include_file(
         "processing file"
     );
 
-    // Function to strip "./" prefix for relative paths.
-    let strip_relative_prefix: for<'a> fn(&'a str) -> &'a str =
-        |s| s.strip_prefix("./").unwrap_or(s);
-
     // Check the glob patterns.
-    let include_bool = if relative_paths {
-        let stripped_path = strip_relative_prefix(relative_path_string);
-        let matches = include_patterns
-            .iter()
-            .any(|pattern| pattern.matches(stripped_path));
-        debug!(patterns = ?include_patterns, matches = matches, "Checking include patterns");
-        matches
-    } else {
-        let matches = include_patterns
-            .iter()
-            .any(|pattern| pattern.matches(path_string));
-        debug!(patterns = ?include_patterns, matches = matches, "Checking include patterns");
-        matches
-    };
+    let include_bool = matches_patterns(path, current_dir, include_patterns, relative_paths);
+    debug!(patterns = ?include_patterns, matches = include_bool, "Checking include patterns");
 
-    let exclude_bool = if relative_paths {
-        let stripped_path = strip_relative_prefix(relative_path_string);
-        let matches = exclude_patterns
-            .iter()
-            .any(|pattern| pattern.matches(stripped_path));
-        debug!(patterns = ?exclude_patterns, matches = matches, "Checking exclude patterns");
-        matches
-    } else {
-        let matches = exclude_patterns
-            .iter()
-            .any(|pattern| pattern.matches(path_string));
-        debug!(patterns = ?exclude_patterns, matches = matches, "Checking exclude patterns");
-        matches
-    };
+    let exclude_bool = matches_patterns(path, current_dir, exclude_patterns, relative_paths);
+    debug!(patterns = ?exclude_patterns, matches = exclude_bool, "Checking exclude patterns");
 
     // Determine if the file should be included.
     let result = match (include_bool, exclude_bool) {
@@ -417,11 +1089,296 @@ fn include_file(
     result
 }
 
+/// Whether a path matches any of the glob patterns.
+///
+/// ### Arguments
+///
+/// - `path`: The canonical path to check.
+/// - `current_dir`: The current directory the relative paths are computed from.
+/// - `patterns`: The pre-compiled glob patterns.
+/// - `relative_paths`: Whether to match the path relative to the current directory instead of
+///   the absolute path.
+///
+/// ### Returns
+///
+/// - `bool`: True if any pattern matches the path.
+///
+fn matches_patterns<'p>(
+    path: &Path,
+    current_dir: &Path,
+    patterns: impl IntoIterator<Item = &'p Pattern>,
+    relative_paths: bool,
+) -> bool {
+    if relative_paths {
+        let relative_path = path.strip_prefix(current_dir).unwrap_or(path);
+        let relative_path_string = relative_path.to_string_lossy();
+        // Strip the "./" prefix for relative paths.
+        let stripped_path = relative_path_string
+            .strip_prefix("./")
+            .unwrap_or(&relative_path_string);
+        patterns
+            .into_iter()
+            .any(|pattern| pattern.matches(stripped_path))
+    } else {
+        let path_string = path.to_string_lossy();
+        patterns
+            .into_iter()
+            .any(|pattern| pattern.matches(&path_string))
+    }
+}
+
+/// Whether a path is part of the explicit set of paths to include.
+///
+/// ### Arguments
+///
+/// - `path`: The path to check.
+/// - `include_paths`: The optional set of absolute paths to include.
+///
+/// ### Returns
+///
+/// - `bool`: True if there is no explicit set of paths or the path is part of it, or if the path
+///   is a directory containing one of the paths.
+///
+fn in_include_paths(path: &Path, include_paths: Option<&HashSet<PathBuf>>) -> bool {
+    match include_paths {
+        Some(include_paths) if path.is_dir() => include_paths
+            .iter()
+            .any(|include_path| include_path.starts_with(path)),
+        Some(include_paths) => include_paths.contains(path),
+        None => true,
+    }
+}
+
+/// Determines whether a file should be skipped without reading the whole file.
+///
+/// ### Arguments
+///
+/// - `path`: The path to the file.
+/// - `max_file_size`: Size in bytes above which the file is skipped.
+/// - `include_generated`: Whether to keep the lockfiles, minified and generated files.
+///
+/// ### Returns
+///
+/// - `Option<SkipReason>`: The reason to skip the file, if any.
+///
+fn skip_reason(
+    path: &Path,
+    max_file_size: Option<u64>,
+    include_generated: bool,
+) -> Option<SkipReason> {
+    let size = fs::metadata(path).ok()?.len();
+    if let Some(limit) = max_file_size {
+        if size > limit {
+            return Some(SkipReason::TooLarge { size, limit });
+        }
+    }
+
+    let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    if !include_generated {
+        if LOCKFILE_NAMES.contains(&file_name) {
+            return Some(SkipReason::Lockfile);
+        }
+        if MINIFIED_FILE_PATTERNS.iter().any(|pattern| {
+            Pattern::new(pattern)
+                .map(|p| p.matches(file_name))
+                .unwrap_or(false)
+        }) {
+            return Some(SkipReason::Minified);
+        }
+    }
+
+    let mut head = Vec::with_capacity(SNIFF_LENGTH);
+    fs::File::open(path)
+        .ok()?
+        .take(SNIFF_LENGTH as u64)
+        .read_to_end(&mut head)
+        .ok()?;
+
+    if is_binary(&head) {
+        return Some(SkipReason::Binary);
+    }
+
+    if !include_generated {
+        let text = String::from_utf8_lossy(&head);
+        if text.lines().take(GENERATED_HEADER_LINES).any(|line| {
+            GENERATED_MARKERS.iter().any(|marker| line.contains(marker))
+                || GENERATED_CODE_HEADER.is_match(line)
+        }) {
+            return Some(SkipReason::Generated);
+        }
+        let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+        if matches!(extension, "js" | "mjs" | "cjs" | "css")
+            && text.lines().any(|line| line.len() > MINIFIED_LINE_LENGTH)
+        {
+            return Some(SkipReason::Minified);
+        }
+    }
+
+    None
+}
+
+/// Checks whether the start of a file looks like binary content.
+///
+/// ### Arguments
+///
+/// - `head`: The first bytes of the file.
+///
+/// ### Returns
+///
+/// - `bool`: True if the bytes contain a NUL byte, start with a known binary magic number or are
+///   mostly invalid UTF-8.
+///
+fn is_binary(head: &[u8]) -> bool {
+    if head.contains(&0)
+        || BINARY_MAGIC_NUMBERS
+            .iter()
+            .any(|magic| head.starts_with(magic))
+    {
+        return true;
+    }
+    let invalid: usize = head.utf8_chunks().map(|chunk| chunk.invalid().len()).sum();
+    invalid * 100 > head.len() * BINARY_INVALID_UTF8_PERCENT
+}
+
+/// Parses a file size with an optional `K`, `M` or `G` suffix (powers of 1024).
+///
+/// ### Arguments
+///
+/// - `size`: The file size, such as `500`, `100K`, `2MB` or `1G`.
+///
+/// ### Returns
+///
+/// - `Result<u64>`: The size in bytes.
+///
+pub fn parse_file_size(size: &str) -> Result<u64> {
+    let normalized = size.trim().to_uppercase();
+    let normalized = normalized.strip_suffix('B').unwrap_or(&normalized);
+    let (number, multiplier) = match normalized.chars().last() {
+        Some('K') => (&normalized[..normalized.len() - 1], 1024),
+        Some('M') => (&normalized[..normalized.len() - 1], 1024 * 1024),
+        Some('G') => (&normalized[..normalized.len() - 1], 1024 * 1024 * 1024),
+        _ => (normalized, 1),
+    };
+    number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| anyhow!("Invalid file size '{}', expected a number of bytes with an optional K, M or G suffix.", size))
+}
+
+/// Formats a file size for display.
+///
+/// ### Arguments
+///
+/// - `size`: The size in bytes.
+///
+/// ### Returns
+///
+/// - `String`: The size with a B, KiB, MiB or GiB unit.
+///
+fn format_file_size(size: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB"];
+    if size < 1024 {
+        return format!("{} B", size);
+    }
+    let mut value = size as f64;
+    let mut unit = "B";
+    for next in UNITS {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = next;
+    }
+    format!("{:.1} {}", value, unit)
+}
+
+/// A line of file content.
+#[derive(Debug, Clone)]
+pub struct SourceLine<'a> {
+    /// The 1-based line number in the original file, or None for lines added in place of elided
+    /// content.
+    pub number: Option<usize>,
+    /// The line content.
+    pub text: Cow<'a, str>,
+}
+
+impl<'a> SourceLine<'a> {
+    /// Splits file content into lines numbered from 1.
+    ///
+    /// ### Arguments
+    ///
+    /// - `content`: The file content.
+    ///
+    /// ### Returns
+    ///
+    /// - `Vec<SourceLine>`: The numbered lines.
+    ///
+    pub fn numbered(content: &'a str) -> Vec<Self> {
+        content
+            .lines()
+            .enumerate()
+            .map(|(idx, line)| Self {
+                number: Some(idx + 1),
+                text: Cow::Borrowed(line),
+            })
+            .collect()
+    }
+
+    /// Selects row ranges of file content, numbered with their original line number. An
+    /// unnumbered elision marker is inserted in place of the lines left out.
+    ///
+    /// ### Arguments
+    ///
+    /// - `content`: The file content.
+    /// - `rows`: The sorted, non-overlapping 0-based and inclusive row ranges to keep.
+    ///
+    /// ### Returns
+    ///
+    /// - `Vec<SourceLine>`: The selected lines.
+    ///
+    pub fn select(content: &'a str, rows: &[(usize, usize)]) -> Vec<Self> {
+        let line_count = content.lines().count();
+        let mut lines = Vec::new();
+        let mut next_row = 0;
+        for &(start, end) in rows {
+            if start > next_row {
+                lines.push(Self::elided());
+            }
+            lines.extend(
+                content
+                    .lines()
+                    .enumerate()
+                    .skip(start)
+                    .take(end + 1 - start)
+                    .map(|(idx, line)| Self {
+                        number: Some(idx + 1),
+                        text: Cow::Borrowed(line),
+                    }),
+            );
+            next_row = end + 1;
+        }
+        if next_row < line_count {
+            lines.push(Self::elided());
+        }
+        lines
+    }
+
+    /// The unnumbered line inserted in place of elided content.
+    fn elided() -> Self {
+        Self {
+            number: None,
+            text: Cow::Borrowed(ELISION_MARKER),
+        }
+    }
+}
+
 /// Wrap the file code content into a markdown code block and add line numbers if applicable.
 ///
 /// ### Arguments
 ///
-/// - `content`: The content to wrap.
+/// - `lines`: The lines to wrap, numbered with their line number in the original file.
 /// - `extension`: The file extension.
 /// - `no_line_numbers`: Whether to skip adding line numbers.
 /// - `no_codeblock`: Whether to wrap the file content or not.
@@ -431,19 +1388,25 @@ fn include_file(
 /// - `String`: The formatted file content.
 ///
 fn wrap_content(
-    content: &str,
+    lines: &[SourceLine],
     extension: &str,
     no_line_numbers: bool,
     no_codeblock: bool,
 ) -> String {
     let mut formatted_block = String::new();
 
-    if !no_line_numbers {
-        for (idx, line) in content.lines().enumerate() {
-            formatted_block.push_str(&format!("{:4} | {}\n", idx + 1, line));
+    for line in lines {
+        if no_line_numbers {
+            formatted_block.push_str(&line.text);
+            formatted_block.push('\n');
+        } else {
+            match line.number {
+                Some(number) => {
+                    formatted_block.push_str(&format!("{:4} | {}\n", number, line.text))
+                }
+                None => formatted_block.push_str(&format!("{:4} | {}\n", "", line.text)),
+            }
         }
-    } else {
-        formatted_block = content.to_owned();
     }
 
     if no_codeblock {
@@ -454,7 +1417,7 @@ fn wrap_content(
         );
         result.push_str(CODE_BLOCK_TICKS);
         result.push_str(extension);
-        result.push_str("\n");
+        result.push('\n');
         result.push_str(&formatted_block);
         result.push_str(CODE_BLOCK_TICKS);
         result
@@ -477,19 +1440,40 @@ fn compile_patterns(patterns: &[String]) -> Result<HashSet<Pattern>> {
 /// ### Arguments
 ///
 /// - `path`: The file path to check
+/// - `sensitive_patterns`: The pre-compiled sensitive file name patterns.
 ///
 /// ### Returns
 ///
 /// - `bool`: True if the file matches a sensitive pattern
 ///
-fn is_sensitive_file(path: &Path) -> bool {
+fn is_sensitive_file(path: &Path, sensitive_patterns: &HashSet<Pattern>) -> bool {
     let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
 
-    SENSITIVE_FILE_PATTERNS.iter().any(|pattern| {
-        Pattern::new(pattern)
-            .map(|p| p.matches(file_name))
-            .unwrap_or(false)
-    })
+    sensitive_patterns
+        .iter()
+        .any(|pattern| pattern.matches(file_name))
+}
+
+/// Checks if a sensitive file has been vetted through the allowlist.
+///
+/// ### Arguments
+///
+/// - `path`: The file path to check.
+/// - `root`: The canonical project root the allowlist patterns are relative to.
+/// - `allowlist_patterns`: The pre-compiled allowlist patterns.
+///
+/// ### Returns
+///
+/// - `bool`: True if the path relative to the project root matches an allowlist pattern.
+///
+fn in_allowlist(path: &Path, root: &Path, allowlist_patterns: &HashSet<Pattern>) -> bool {
+    path.strip_prefix(root)
+        .map(|relative_path| {
+            allowlist_patterns
+                .iter()
+                .any(|pattern| pattern.matches_path(relative_path))
+        })
+        .unwrap_or(false)
 }
 
 /// Prompts the user to confirm whether to continue when sensitive files are detected.
@@ -520,7 +1504,7 @@ pub fn prompt_for_sensitive_files(sensitive_files: &[String]) -> bool {
         "These files may contain secrets or credentials.".yellow()
     );
     eprint!("{}", "Continue anyway? [y/N] ".bold());
-    io::stdout().flush().unwrap();
+    io::stderr().flush().unwrap();
 
     let mut response = String::new();
     io::stdin().read_line(&mut response).unwrap();
@@ -533,14 +1517,239 @@ pub fn prompt_for_sensitive_files(sensitive_files: &[String]) -> bool {
 /// ### Arguments
 ///
 /// - `path`: The path to check.
+/// - `ignore_patterns`: The pre-compiled ignore list patterns.
 ///
 /// ### Returns
 ///
 /// - `bool`: True if the path should be excluded
 ///
-fn in_ignore_list(path: &Path) -> bool {
+fn in_ignore_list(path: &Path, ignore_patterns: &HashSet<Pattern>) -> bool {
     path.file_name()
         .and_then(|n| n.to_str())
-        .map(|name| IGNORE_LIST.contains(&name))
+        .map(|name| ignore_patterns.iter().any(|pattern| pattern.matches(name)))
         .unwrap_or(false)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiktoken_rs::cl100k_base;
+
+    /// A temporary directory removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("codeprompt-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            for (path, content) in files {
+                let path = dir.join(path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, content).unwrap();
+            }
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn paths(files: &[serde_json::Value]) -> Vec<&str> {
+        files
+            .iter()
+            .map(|file| file["path"].as_str().unwrap())
+            .collect()
+    }
+
+    fn patterns(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|pattern| pattern.to_string()).collect()
+    }
+
+    fn range(start: usize, end: usize) -> LineRange {
+        LineRange { start, end }
+    }
+
+    #[test]
+    fn splits_line_ranges_off_the_include_patterns() {
+        let (include, ranged) =
+            split_line_ranges(patterns(&["src/main.rs:100-200", "*.md", "lib.rs:7"])).unwrap();
+
+        assert_eq!(include, patterns(&["src/main.rs", "*.md", "lib.rs"]));
+        assert_eq!(ranged.len(), 2);
+        assert_eq!(ranged[0].pattern, "src/main.rs");
+        assert_eq!(ranged[0].range, range(100, 200));
+        assert_eq!(ranged[1].pattern, "lib.rs");
+        assert_eq!(ranged[1].range, range(7, 7));
+    }
+
+    #[test]
+    fn keeps_patterns_without_a_numeric_range() {
+        let input = patterns(&["C:/src/*.rs", "notes:draft", "file:1-x"]);
+
+        let (include, ranged) = split_line_ranges(input.clone()).unwrap();
+
+        assert_eq!(include, input);
+        assert!(ranged.is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_line_ranges() {
+        assert!(split_line_ranges(patterns(&["main.rs:0-5"])).is_err());
+        assert!(split_line_ranges(patterns(&["main.rs:20-10"])).is_err());
+    }
+
+    #[test]
+    fn merges_overlapping_and_adjacent_line_ranges() {
+        let merged = merge_line_ranges(
+            vec![range(20, 30), range(1, 5), range(6, 8), range(25, 40)],
+            100,
+        );
+
+        assert_eq!(merged, vec![range(1, 8), range(20, 40)]);
+    }
+
+    #[test]
+    fn clamps_line_ranges_to_the_file() {
+        let merged = merge_line_ranges(vec![range(8, 15), range(20, 30)], 10);
+
+        assert_eq!(merged, vec![range(8, 10)]);
+    }
+
+    #[test]
+    fn keeps_the_files_that_fit_in_the_budget() {
+        let files = vec![
+            json!({ "path": "a", "cost": 30 }),
+            json!({ "path": "b", "cost": 50 }),
+            json!({ "path": "c", "cost": 20 }),
+        ];
+        let cost = |file: &serde_json::Value| Ok(file["cost"].as_u64().unwrap() as usize);
+
+        let (kept, omitted) = apply_token_budget(files, 60, cost).unwrap();
+
+        // The large file doesn't prevent the smaller file after it from being kept.
+        assert_eq!(paths(&kept), vec!["a", "c"]);
+        assert_eq!(omitted, vec!["b"]);
+    }
+
+    #[test]
+    fn omits_the_files_without_counting_them_once_the_budget_is_used() {
+        let files = vec![json!({ "path": "a" }), json!({ "path": "b" })];
+        let cost = |file: &serde_json::Value| match file["path"].as_str() {
+            Some("a") => Ok(10),
+            _ => Err(anyhow!("counted")),
+        };
+
+        let (kept, omitted) = apply_token_budget(files, 10, cost).unwrap();
+
+        assert_eq!(paths(&kept), vec!["a"]);
+        assert_eq!(omitted, vec!["b"]);
+    }
+
+    #[test]
+    fn reports_the_errors_of_the_cost_function() {
+        let files = vec![json!({ "path": "a" })];
+
+        let result = apply_token_budget(files, 10, |_| Err(anyhow!("render failed")));
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn subtracts_the_source_tree_from_the_budget() {
+        let dir = TempDir::new(
+            "budget",
+            &[
+                ("a.txt", "first file\n"),
+                ("b.txt", &"second file\n".repeat(50)),
+            ],
+        );
+        let bpe = cl100k_base().unwrap();
+        let cost = |file: &serde_json::Value| Ok(file["token_count"].as_u64().unwrap() as usize);
+        let config = TraversalConfig {
+            relative_paths: true,
+            tokenizer: Some(&bpe),
+            file_cost: Some(FileCost(&cost)),
+            ..Default::default()
+        };
+        let all = traverse_directory(&dir.0, &config).unwrap();
+        let tree_tokens = bpe.encode_with_special_tokens(&all.tree).len();
+        let file_tokens: usize = all.files.iter().map(|file| cost(file).unwrap()).sum();
+
+        let config = TraversalConfig {
+            max_tokens: Some(tree_tokens + file_tokens),
+            ..config
+        };
+        let traversal = traverse_directory(&dir.0, &config).unwrap();
+        assert_eq!(traversal.files.len(), 2);
+        assert!(traversal.omitted.is_empty());
+
+        // Without room for the source tree, the last file is left out.
+        let config = TraversalConfig {
+            max_tokens: Some(file_tokens),
+            ..config
+        };
+        let traversal = traverse_directory(&dir.0, &config).unwrap();
+        assert_eq!(traversal.files.len(), 1);
+        assert_eq!(traversal.omitted.len(), 1);
+        assert!(traversal.omitted[0].ends_with("b.txt"));
+    }
+
+    #[test]
+    fn drops_line_ranges_past_the_end_of_the_file() {
+        assert!(merge_line_ranges(vec![range(11, 20), range(50, 60)], 10).is_empty());
+    }
+
+    #[test]
+    fn leaves_out_the_files_whose_line_ranges_are_out_of_bounds() {
+        let dir = TempDir::new(
+            "line-ranges",
+            &[
+                ("short.rs", "fn a() {}\nfn b() {}\n"),
+                ("long.rs", &"let x = 1;\n".repeat(20)),
+            ],
+        );
+        let line_ranges = vec![RangedPattern {
+            pattern: "*.rs".to_owned(),
+            range: range(15, 30),
+        }];
+        let config = TraversalConfig {
+            relative_paths: true,
+            line_ranges: &line_ranges,
+            ..Default::default()
+        };
+
+        let traversal = traverse_directory(&dir.0, &config).unwrap();
+
+        assert_eq!(traversal.files.len(), 1);
+        assert!(traversal.files[0]["path"]
+            .as_str()
+            .unwrap()
+            .ends_with("long.rs"));
+        assert_eq!(
+            traversal.files[0]["line_ranges"],
+            json!([{ "start": 15, "end": 20 }])
+        );
+        assert_eq!(traversal.out_of_range.len(), 1);
+        assert!(traversal.out_of_range[0].ends_with("short.rs"));
+    }
+
+    #[test]
+    fn parses_file_sizes() {
+        assert_eq!(parse_file_size("500").unwrap(), 500);
+        assert_eq!(parse_file_size("100K").unwrap(), 100 * 1024);
+        assert_eq!(parse_file_size(" 2mb ").unwrap(), 2 * 1024 * 1024);
+        assert_eq!(parse_file_size("1G").unwrap(), 1024 * 1024 * 1024);
+    }
+
+    #[test]
+    fn rejects_invalid_and_overflowing_file_sizes() {
+        assert!(parse_file_size("").is_err());
+        assert!(parse_file_size("10T").is_err());
+        assert!(parse_file_size("-1K").is_err());
+        assert!(parse_file_size("99999999999999G").is_err());
+    }
+}
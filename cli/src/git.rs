@@ -3,10 +3,14 @@
 //! Module that handles the Git operation functionality.
 
 use anyhow::{anyhow, Context, Error, Result};
-use git2::{DiffFormat::Patch, DiffOptions, Repository};
+use git2::{
+    Commit, Delta, Diff, DiffFormat::Patch, DiffOptions, Oid, Repository, RevparseMode, Sort, Time,
+    Tree,
+};
 use regex::Regex;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 
 /// Struct to represent a parsed Github issue.
 #[derive(Debug, Deserialize, Serialize)]
@@ -23,40 +27,153 @@ pub struct Issue {
     pub html_url: String,
 }
 
+/// Struct to represent a commit in the git log.
+#[derive(Debug, Serialize)]
+pub struct LogEntry {
+    /// The full commit hash.
+    pub hash: String,
+    /// The commit author name and email.
+    pub author: String,
+    /// The commit date in the author's timezone.
+    pub date: String,
+    /// The first line of the commit message.
+    pub subject: String,
+    /// The rest of the commit message.
+    pub body: String,
+    /// The files changed by the commit.
+    pub files: Vec<String>,
+}
+
+/// The commits to include in the git log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogSource {
+    /// The most recent commits reachable from HEAD.
+    Recent(usize),
+    /// The commits in a revision range given as `A..B` or `A...B` (equivalent to
+    /// `git log A..B` and `git log A...B`).
+    Range(String),
+}
+
+/// The source of a git diff.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffSource {
+    /// Staged changes (equivalent to `git diff --staged`).
+    Staged,
+    /// Unstaged changes (equivalent to `git diff`).
+    Unstaged,
+    /// Both staged and unstaged changes.
+    StagedAndUnstaged,
+    /// Changes between a revision and the working tree, including staged changes (equivalent to
+    /// `git diff <rev>`).
+    Base(String),
+    /// Changes between two revisions given as `A..B`, or from the merge base of both revisions to
+    /// `B` when given as `A...B` (equivalent to `git diff A..B` and `git diff A...B`).
+    Range(String),
+}
+
+impl DiffSource {
+    /// Builds the diff source from the command line flags.
+    ///
+    /// ### Arguments
+    ///
+    /// - `staged`: Whether the staged changes were requested.
+    /// - `unstaged`: Whether the unstaged changes were requested.
+    /// - `base`: The revision to diff the working tree against.
+    /// - `range`: The revision range to diff.
+    ///
+    /// ### Returns
+    ///
+    /// - `Option<DiffSource>`: The diff source, or None if no diff was requested.
+    ///
+    pub fn from_flags(
+        staged: bool,
+        unstaged: bool,
+        base: Option<&str>,
+        range: Option<&str>,
+    ) -> Option<Self> {
+        match (staged, unstaged, base, range) {
+            (_, _, _, Some(range)) => Some(Self::Range(range.to_owned())),
+            (_, _, Some(base), None) => Some(Self::Base(base.to_owned())),
+            (true, true, None, None) => Some(Self::StagedAndUnstaged),
+            (true, false, None, None) => Some(Self::Staged),
+            (false, true, None, None) => Some(Self::Unstaged),
+            (false, false, None, None) => None,
+        }
+    }
+}
+
 /// Generates a git diff in the repository.
 ///
 /// ### Arguments
 ///
-/// - `path`: The path to the repository.
-/// - `mode`: The git diff mode to run:
-///   - `0`: for diff_tree_to_index (staged changes)
-///   - `1`: for diff_index_to_workdir (unstaged changes)
-///   - `2`: for both staged and unstaged changes
+/// - `repo`: The repository.
+/// - `source`: The source of the diff.
 ///
 /// ### Returns
 ///
 /// - `Result<String, anyhow::Error>`: A string containing the git diff on success, or an Error if
-/// the diff generation fails.
+///   the diff generation fails.
 ///
-pub fn git_diff(repo: &Repository, mode: u8) -> Result<String, Error> {
-    // Resolve the reference pointed at by HEAD.
-    let head = repo.head().context("Failed to get the repository head.")?;
-    let tree = head
-        .peel_to_tree()
-        .context("Failed to peel tree at head.")?;
+pub fn git_diff(repo: &Repository, source: &DiffSource) -> Result<String, Error> {
+    let diff = build_diff(repo, source)?;
+
+    // Using a Vec because Vec's grow more efficiently than Strings.
+    let mut diff_text = Vec::new();
+    diff.print(Patch, |_delta, _hunk, line| {
+        let prefix = match line.origin() {
+            '+' => b'+',
+            '-' => b'-',
+            _ => b' ',
+        };
+        diff_text.push(prefix);
+        diff_text.extend_from_slice(line.content());
+        true
+    })
+    .context("Failed to generate diff")?;
 
+    Ok(String::from_utf8_lossy(&diff_text).into_owned())
+}
+
+/// Lists the files touched by a git diff that still exist after the change.
+///
+/// ### Arguments
+///
+/// - `repo`: The repository.
+/// - `source`: The source of the diff.
+///
+/// ### Returns
+///
+/// - `Result<Vec<PathBuf>, anyhow::Error>`: The absolute paths of the changed files, or an Error
+///   if the diff generation fails.
+///
+pub fn git_changed_files(repo: &Repository, source: &DiffSource) -> Result<Vec<PathBuf>, Error> {
+    let workdir = repo
+        .workdir()
+        .context("Failed to get the repository working directory.")?
+        .canonicalize()?;
+    let diff = build_diff(repo, source)?;
+
+    Ok(diff
+        .deltas()
+        .filter(|delta| delta.status() != Delta::Deleted)
+        .filter_map(|delta| delta.new_file().path().map(|path| workdir.join(path)))
+        .collect())
+}
+
+/// Builds the git diff for a diff source.
+fn build_diff<'r>(repo: &'r Repository, source: &DiffSource) -> Result<Diff<'r>, Error> {
     let mut opts = DiffOptions::new();
 
-    let diff = match mode {
-        0 => repo
-            .diff_tree_to_index(Some(&tree), None, Some(&mut opts))
+    let diff = match source {
+        DiffSource::Staged => repo
+            .diff_tree_to_index(Some(&head_tree(repo)?), None, Some(&mut opts))
             .context("Failed to generate tree to index diff.")?,
-        1 => repo
+        DiffSource::Unstaged => repo
             .diff_index_to_workdir(None, Some(&mut opts))
             .context("Failed to generate index to workdir diff.")?,
-        2 => {
+        DiffSource::StagedAndUnstaged => {
             let mut diff = repo
-                .diff_tree_to_index(Some(&tree), None, Some(&mut opts))
+                .diff_tree_to_index(Some(&head_tree(repo)?), None, Some(&mut opts))
                 .context("Failed to generate tree to index diff.")?;
             diff.merge(
                 &repo
@@ -65,29 +182,203 @@ pub fn git_diff(repo: &Repository, mode: u8) -> Result<String, Error> {
             )?;
             diff
         }
-        _ => {
-            return Err(Error::msg(format!(
-                "Invalid git diff mode. Expected 0, 1, or 2. Got {}.",
-                mode
-            )))
+        DiffSource::Base(rev) => {
+            let tree = repo
+                .revparse_single(rev)
+                .and_then(|object| object.peel_to_tree())
+                .with_context(|| format!("Failed to resolve revision '{}'.", rev))?;
+            repo.diff_tree_to_workdir_with_index(Some(&tree), Some(&mut opts))
+                .with_context(|| format!("Failed to generate diff against '{}'.", rev))?
+        }
+        DiffSource::Range(range) => {
+            let (from, to) = range_trees(repo, range)?;
+            repo.diff_tree_to_tree(Some(&from), Some(&to), Some(&mut opts))
+                .with_context(|| format!("Failed to generate diff for range '{}'.", range))?
         }
     };
 
-    // Using a Vec because Vec's grow more efficiently than Strings.
-    let mut diff_text = Vec::new();
-    diff.print(Patch, |_delta, _hunk, line| {
-        let prefix = match line.origin() {
-            '+' => b'+',
-            '-' => b'-',
-            _ => b' ',
-        };
-        diff_text.push(prefix);
-        diff_text.extend_from_slice(line.content());
-        true
+    Ok(diff)
+}
+
+/// Resolves the tree HEAD points at.
+fn head_tree(repo: &Repository) -> Result<Tree<'_>, Error> {
+    // Resolve the reference pointed at by HEAD.
+    let head = repo.head().context("Failed to get the repository head.")?;
+    head.peel_to_tree().context("Failed to peel tree at head.")
+}
+
+/// Resolves both ends of a revision range.
+///
+/// ### Arguments
+///
+/// - `repo`: The repository.
+/// - `range`: The revision range, either `A..B` or `A...B`.
+///
+/// ### Returns
+///
+/// - `Result<(Oid, Oid, bool), Error>`: The revisions the range goes from and to, and whether it
+///   was given as `A...B` and so starts at the merge base of both revisions.
+///
+fn parse_range(repo: &Repository, range: &str) -> Result<(Oid, Oid, bool), Error> {
+    let revspec = repo
+        .revparse(range)
+        .with_context(|| format!("Failed to resolve revision range '{}'.", range))?;
+    match (revspec.from(), revspec.to()) {
+        (Some(from), Some(to)) if revspec.mode().contains(RevparseMode::RANGE) => Ok((
+            from.id(),
+            to.id(),
+            revspec.mode().contains(RevparseMode::MERGE_BASE),
+        )),
+        _ => Err(anyhow!(
+            "Invalid revision range '{}', expected A..B or A...B.",
+            range
+        )),
+    }
+}
+
+/// Resolves the trees at both ends of a revision range.
+///
+/// ### Arguments
+///
+/// - `repo`: The repository.
+/// - `range`: The revision range, either `A..B` or `A...B`.
+///
+/// ### Returns
+///
+/// - `Result<(Tree, Tree), Error>`: The trees to diff from and to. For `A...B` the first tree is
+///   the tree of the merge base of both revisions.
+///
+fn range_trees<'r>(repo: &'r Repository, range: &str) -> Result<(Tree<'r>, Tree<'r>), Error> {
+    let (from, to, merge_base) = parse_range(repo, range)?;
+
+    let to_commit = repo.find_commit(to)?;
+    let from_tree = if merge_base {
+        let merge_base = repo
+            .merge_base(from, to)
+            .with_context(|| format!("Failed to find the merge base of '{}'.", range))?;
+        repo.find_commit(merge_base)?.tree()?
+    } else {
+        repo.find_object(from, None)?.peel_to_tree()?
+    };
+
+    Ok((from_tree, to_commit.tree()?))
+}
+
+/// Walks the commits of the repository to build the git log.
+///
+/// ### Arguments
+///
+/// - `repo`: The repository.
+/// - `source`: The commits to include.
+///
+/// ### Returns
+///
+/// - `Result<Vec<LogEntry>, anyhow::Error>`: The commits from newest to oldest on success, or an
+///   Error if the revisions can't be resolved.
+///
+pub fn git_log(repo: &Repository, source: &LogSource) -> Result<Vec<LogEntry>, Error> {
+    let mut revwalk = repo
+        .revwalk()
+        .context("Failed to create the revision walker.")?;
+    revwalk.set_sorting(Sort::TIME)?;
+
+    let limit = match source {
+        LogSource::Recent(count) => {
+            revwalk
+                .push_head()
+                .context("Failed to get the repository head.")?;
+            *count
+        }
+        LogSource::Range(range) => {
+            let (from, to, merge_base) = parse_range(repo, range)?;
+            revwalk.push(to)?;
+            if merge_base {
+                revwalk.push(from)?;
+                revwalk.hide(repo.merge_base(from, to)?)?;
+            } else {
+                revwalk.hide(from)?;
+            }
+            usize::MAX
+        }
+    };
+
+    revwalk
+        .take(limit)
+        .map(|oid| {
+            let commit = repo.find_commit(oid?)?;
+            log_entry(repo, &commit)
+        })
+        .collect()
+}
+
+/// Builds the git log entry for a commit.
+fn log_entry(repo: &Repository, commit: &Commit) -> Result<LogEntry, Error> {
+    let author = commit.author();
+    let message = commit.message().unwrap_or_default();
+    let (subject, body) = message.split_once('\n').unwrap_or((message, ""));
+
+    // Diff against the first parent, or against an empty tree for the root commit.
+    let parent_tree = match commit.parents().next() {
+        Some(parent) => Some(parent.tree()?),
+        None => None,
+    };
+    let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), None)?;
+    let files = diff
+        .deltas()
+        .filter_map(|delta| delta.new_file().path().or_else(|| delta.old_file().path()))
+        .map(|path| path.display().to_string())
+        .collect();
+
+    Ok(LogEntry {
+        hash: commit.id().to_string(),
+        author: format!(
+            "{} <{}>",
+            author.name().unwrap_or_default(),
+            author.email().unwrap_or_default()
+        ),
+        date: format_time(&author.when()),
+        subject: subject.trim().to_owned(),
+        body: body.trim().to_owned(),
+        files,
     })
-    .context("Failed to generate diff")?;
+}
 
-    Ok(String::from_utf8_lossy(&diff_text).into_owned())
+/// Formats a git timestamp as `YYYY-MM-DD HH:MM:SS +HHMM` in its own timezone.
+fn format_time(time: &Time) -> String {
+    let offset_minutes = i64::from(time.offset_minutes());
+    let local_seconds = time.seconds() + offset_minutes * 60;
+    let days = local_seconds.div_euclid(86_400);
+    let seconds_of_day = local_seconds.rem_euclid(86_400);
+
+    // Converts the days since the unix epoch to a civil date, see
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} {}{:02}{:02}",
+        year,
+        month,
+        day,
+        seconds_of_day / 3_600,
+        seconds_of_day % 3_600 / 60,
+        seconds_of_day % 60,
+        if offset_minutes < 0 { '-' } else { '+' },
+        offset_minutes.abs() / 60,
+        offset_minutes.abs() % 60
+    )
 }
 
 /// Extracts the owner and repository name from a Git repository.
@@ -102,7 +393,7 @@ pub fn git_diff(repo: &Repository, mode: u8) -> Result<String, Error> {
 /// ### Returns
 ///
 /// - `Result<(String, String), Error>`: A tuple containing the owner and repository name on
-/// success, or an Error if the information cannot be extracted.
+///   success, or an Error if the information cannot be extracted.
 ///
 pub fn get_repo_info(repo: &Repository) -> Result<(String, String), Error> {
     let remote = repo
@@ -117,11 +408,11 @@ pub fn get_repo_info(repo: &Repository) -> Result<(String, String), Error> {
 
     let re = Regex::new(r"github\.com[:/]([^/]+)/([^/]+)(?:\.git)?$").unwrap();
     re.captures(url)
-        .and_then(|cap| {
-            Some((
+        .map(|cap| {
+            (
                 cap.get(1).unwrap().as_str().to_owned(),
                 cap.get(2).unwrap().as_str().to_owned(),
-            ))
+            )
         })
         .context("Unable to parse Github repository information.")
 }
@@ -137,7 +428,7 @@ pub fn get_repo_info(repo: &Repository) -> Result<(String, String), Error> {
 /// ### Returns
 ///
 /// - `Result<Issue, Error>`: An Issue struct containing the fetched issue details on success, or
-/// an Error if the API request fails or the response cannot be parsed.
+///   an Error if the API request fails or the response cannot be parsed.
 ///
 /// ### Errors
 ///
@@ -178,3 +469,273 @@ pub async fn fetch_github_issue(
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::{IndexAddOption, Signature};
+    use std::fs;
+
+    /// A git repository in a temporary directory that is removed on drop.
+    struct TempRepo {
+        repo: Repository,
+        dir: PathBuf,
+    }
+
+    impl TempRepo {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "codeprompt-git-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            let repo = Repository::init(&dir).unwrap();
+            Self { repo, dir }
+        }
+
+        fn write(&self, path: &str, content: &str) {
+            fs::write(self.dir.join(path), content).unwrap();
+        }
+
+        fn remove(&self, path: &str) {
+            fs::remove_file(self.dir.join(path)).unwrap();
+        }
+
+        /// Stages every change of the working tree, including deleted files.
+        fn stage(&self) {
+            let mut index = self.repo.index().unwrap();
+            index.add_all(["*"], IndexAddOption::DEFAULT, None).unwrap();
+            index.update_all(["*"], None).unwrap();
+            index.write().unwrap();
+        }
+
+        /// Commits the index on top of HEAD.
+        fn commit(&self, message: &str) -> Oid {
+            let signature = Signature::new("Test", "test@example.com", &Time::new(0, 0)).unwrap();
+            let tree = self
+                .repo
+                .find_tree(self.repo.index().unwrap().write_tree().unwrap())
+                .unwrap();
+            let parent = self
+                .repo
+                .head()
+                .ok()
+                .map(|head| head.peel_to_commit().unwrap());
+            self.repo
+                .commit(
+                    Some("HEAD"),
+                    &signature,
+                    &signature,
+                    message,
+                    &tree,
+                    &parent.iter().collect::<Vec<_>>(),
+                )
+                .unwrap()
+        }
+
+        /// Commits a new file on a `feature` branch forked from `base`, without touching HEAD.
+        fn commit_feature(&self, base: Oid, path: &str, content: &str) -> Oid {
+            let signature = Signature::new("Test", "test@example.com", &Time::new(0, 0)).unwrap();
+            let base = self.repo.find_commit(base).unwrap();
+            let blob = self.repo.blob(content.as_bytes()).unwrap();
+            let mut builder = self.repo.treebuilder(Some(&base.tree().unwrap())).unwrap();
+            builder.insert(path, blob, 0o100644).unwrap();
+            let tree = self.repo.find_tree(builder.write().unwrap()).unwrap();
+            self.repo
+                .commit(
+                    Some("refs/heads/feature"),
+                    &signature,
+                    &signature,
+                    "Add the feature",
+                    &tree,
+                    &[&base],
+                )
+                .unwrap()
+        }
+    }
+
+    impl Drop for TempRepo {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// Builds a repository where `main` and `feature` both branch off the first commit: `main`
+    /// changes `a.txt` and `feature` adds `c.txt`.
+    fn forked_repo(name: &str) -> TempRepo {
+        let repo = TempRepo::new(name);
+        repo.write("a.txt", "one\n");
+        repo.write("b.txt", "bee\n");
+        repo.stage();
+        let first = repo.commit("First");
+        repo.write("a.txt", "two\n");
+        repo.stage();
+        repo.commit("Second");
+        repo.commit_feature(first, "c.txt", "sea\n");
+        repo
+    }
+
+    fn tree_files(tree: &Tree) -> Vec<String> {
+        tree.iter()
+            .map(|entry| entry.name().unwrap().to_owned())
+            .collect()
+    }
+
+    fn file_names(paths: &[PathBuf]) -> Vec<&str> {
+        let mut names: Vec<&str> = paths
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
+    #[test]
+    fn builds_the_diff_source_from_the_flags() {
+        assert_eq!(DiffSource::from_flags(false, false, None, None), None);
+        assert_eq!(
+            DiffSource::from_flags(true, false, None, None),
+            Some(DiffSource::Staged)
+        );
+        assert_eq!(
+            DiffSource::from_flags(false, true, None, None),
+            Some(DiffSource::Unstaged)
+        );
+        assert_eq!(
+            DiffSource::from_flags(true, true, None, None),
+            Some(DiffSource::StagedAndUnstaged)
+        );
+        assert_eq!(
+            DiffSource::from_flags(true, true, Some("main"), None),
+            Some(DiffSource::Base("main".to_owned()))
+        );
+        assert_eq!(
+            DiffSource::from_flags(true, false, Some("main"), Some("a..b")),
+            Some(DiffSource::Range("a..b".to_owned()))
+        );
+    }
+
+    #[test]
+    fn resolves_the_trees_of_a_range() {
+        let repo = forked_repo("range-trees");
+
+        let (from, to) = range_trees(&repo.repo, "feature..HEAD").unwrap();
+        assert_eq!(tree_files(&from), ["a.txt", "b.txt", "c.txt"]);
+        assert_eq!(tree_files(&to), ["a.txt", "b.txt"]);
+
+        // The merge base of both branches is the first commit, which doesn't have `c.txt`.
+        let (from, to) = range_trees(&repo.repo, "feature...HEAD").unwrap();
+        assert_eq!(tree_files(&from), ["a.txt", "b.txt"]);
+        assert_eq!(tree_files(&to), ["a.txt", "b.txt"]);
+
+        let error = range_trees(&repo.repo, "HEAD").unwrap_err();
+        assert!(error.to_string().contains("expected A..B or A...B"));
+    }
+
+    #[test]
+    fn diffs_a_range() {
+        let repo = forked_repo("range-diff");
+
+        let diff = git_diff(&repo.repo, &DiffSource::Range("feature..HEAD".to_owned())).unwrap();
+        assert!(diff.contains("-one") && diff.contains("+two"));
+        assert!(diff.contains("-sea"));
+    }
+
+    #[test]
+    fn diffs_a_range_from_the_merge_base() {
+        let repo = forked_repo("merge-base-diff");
+
+        let diff = git_diff(&repo.repo, &DiffSource::Range("feature...HEAD".to_owned())).unwrap();
+        assert!(diff.contains("-one") && diff.contains("+two"));
+        assert!(!diff.contains("sea"));
+
+        let diff = git_diff(&repo.repo, &DiffSource::Range("HEAD...feature".to_owned())).unwrap();
+        assert!(diff.contains("+sea"));
+        assert!(!diff.contains("two"));
+    }
+
+    #[test]
+    fn diffs_the_working_tree_against_a_base() {
+        let repo = forked_repo("base-diff");
+        repo.write("a.txt", "three\n");
+        repo.stage();
+        repo.write("b.txt", "bees\n");
+
+        let diff = git_diff(&repo.repo, &DiffSource::Base("HEAD~1".to_owned())).unwrap();
+        assert!(diff.contains("-one") && diff.contains("+three"));
+        assert!(diff.contains("-bee") && diff.contains("+bees"));
+        assert!(!diff.contains("two"));
+    }
+
+    #[test]
+    fn diffs_the_staged_and_unstaged_changes() {
+        let repo = forked_repo("worktree-diff");
+        repo.write("a.txt", "three\n");
+        repo.stage();
+        repo.write("b.txt", "bees\n");
+
+        let staged = git_diff(&repo.repo, &DiffSource::Staged).unwrap();
+        assert!(staged.contains("+three") && !staged.contains("bees"));
+
+        let unstaged = git_diff(&repo.repo, &DiffSource::Unstaged).unwrap();
+        assert!(unstaged.contains("+bees") && !unstaged.contains("three"));
+
+        let both = git_diff(&repo.repo, &DiffSource::StagedAndUnstaged).unwrap();
+        assert!(both.contains("+three") && both.contains("+bees"));
+    }
+
+    #[test]
+    fn lists_the_changed_files_without_the_deleted_ones() {
+        let repo = forked_repo("changed-files");
+        repo.write("a.txt", "three\n");
+        repo.remove("b.txt");
+        repo.write("d.txt", "dee\n");
+        repo.stage();
+
+        let files = git_changed_files(&repo.repo, &DiffSource::Staged).unwrap();
+        assert_eq!(file_names(&files), ["a.txt", "d.txt"]);
+        let workdir = repo.dir.canonicalize().unwrap();
+        assert!(files.iter().all(|path| path.starts_with(&workdir)));
+
+        let files = git_changed_files(&repo.repo, &DiffSource::Range("HEAD~1..feature".to_owned()))
+            .unwrap();
+        assert_eq!(file_names(&files), ["c.txt"]);
+
+        // The second commit changed `a.txt`, and `c.txt` is only on the feature branch.
+        let files =
+            git_changed_files(&repo.repo, &DiffSource::Range("feature..HEAD".to_owned())).unwrap();
+        assert_eq!(file_names(&files), ["a.txt"]);
+    }
+
+    #[test]
+    fn formats_the_epoch() {
+        assert_eq!(format_time(&Time::new(0, 0)), "1970-01-01 00:00:00 +0000");
+    }
+
+    #[test]
+    fn formats_a_leap_day() {
+        assert_eq!(
+            format_time(&Time::new(1_709_208_000, 60)),
+            "2024-02-29 13:00:00 +0100"
+        );
+    }
+
+    #[test]
+    fn formats_a_negative_offset() {
+        // 2024-03-01 02:30:00 UTC is still the leap day in UTC-05:30.
+        assert_eq!(
+            format_time(&Time::new(1_709_260_200, -330)),
+            "2024-02-29 21:00:00 -0530"
+        );
+    }
+
+    #[test]
+    fn formats_a_timestamp_before_the_epoch() {
+        assert_eq!(format_time(&Time::new(-1, 0)), "1969-12-31 23:59:59 +0000");
+        assert_eq!(
+            format_time(&Time::new(-14_182_940, 0)),
+            "1969-07-20 20:17:40 +0000"
+        );
+    }
+}
@@ -4,13 +4,22 @@ pub mod constants {
     //! Module for the codeprompt constants.
     pub const DEFAULT_TEMPLATE_NAME: &str = "default";
     pub const CUSTOM_TEMPLATE_NAME: &str = "custom";
+    pub const XML_TEMPLATE_NAME: &str = "xml";
+    pub const PLAIN_TEMPLATE_NAME: &str = "plain";
     pub const PROGRESS_SPINNER_TICK: u64 = 120;
 }
 
+pub mod clipboard;
+pub mod compact;
+pub mod config;
 pub mod files;
 pub mod git;
 pub mod logging;
+pub mod outline;
+pub mod secrets;
 pub mod spinner;
+pub mod split;
+pub mod symbols;
 pub mod template;
 pub mod tokenizer;
 pub mod validation;
@@ -18,10 +27,17 @@ pub mod validation;
 pub mod prelude {
     //! Easy import prelude module.
     pub use crate::files::{
-        basename, check_sensitive_files, parse_comma_delim_patterns, traverse_directory,
+        basename, check_sensitive_files, parse_comma_delim_patterns, parse_file_size,
+        split_line_ranges, traverse_directory, FilePatterns, LineRange, RangedPattern, SkipReason,
+        SkippedFile, Traversal, TraversalConfig,
+    };
+    pub use crate::git::{
+        fetch_github_issue, get_repo_info, git_changed_files, git_diff, git_log, DiffSource,
+        LogSource,
     };
-    pub use crate::git::{fetch_github_issue, get_repo_info, git_diff};
     pub use crate::spinner::setup_spinner;
-    pub use crate::template::{get_template, render_template, setup_handlebars_registry};
+    pub use crate::template::{
+        get_template, render_output, render_template, setup_handlebars_registry, OutputFormat,
+    };
     pub use crate::tokenizer::tokenizer_init;
 }
@@ -1,21 +1,52 @@
 use anyhow::anyhow;
 use anyhow::{Context, Error, Result};
-use arboard::Clipboard;
-use clap::{ArgAction, Command, CommandFactory, Parser, Subcommand};
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, Command, CommandFactory, FromArgMatches, Parser, Subcommand};
 use clap_complete::{generate, Generator, Shell};
-use codeprompt::files::prompt_for_sensitive_files;
+use codeprompt::clipboard::{copy_to_clipboard, ClipboardBackend};
+use codeprompt::config::{Config, Defaults};
+use codeprompt::files::{prompt_for_sensitive_files, FileCost};
 use codeprompt::logging;
 use codeprompt::prelude::*;
-use codeprompt::validation::{validate_clipboard_copy, validate_token_count, ValidationConfig};
+use codeprompt::split::{chunk_path, split_prompt};
+use codeprompt::symbols::SymbolQuery;
+use codeprompt::template::{
+    find_missing_variables, merge_template_vars, parse_template_var, BUILTIN_TEMPLATES,
+};
+use codeprompt::validation::{
+    validate_clipboard_copy, validate_token_count, ValidationConfig, ValidationWarning,
+    CLIPBOARD_TOKEN_THRESHOLD, EXIT_FAILURE, TOKEN_WARNING_THRESHOLD,
+};
 use colored::*;
 use git2::Repository;
 use serde_json::json;
-use std::io::Write;
+use std::collections::HashSet;
+use std::io::{IsTerminal, Write};
 use std::path::PathBuf;
 
+/// Exit codes listed at the end of the long help.
+const EXIT_CODES_HELP: &str = "\
+Exit codes:
+  0   Success
+  1   Any other error, or the operation was cancelled at a prompt
+  2   Git features were used outside of a git repository
+
+  With --fail-on-warnings, or --fail-on-sensitive for 18 and 19:
+  10  A git diff option was used with a template that doesn't render the diff
+  11  A git log option was used with a template that doesn't render the log
+  12  The issue option was used with a template that doesn't render the issue
+  13  The token count is over the token warning threshold
+  14  Files were left out to stay within the --max-tokens budget
+  15  The template references variables without a value
+  16  Symbols passed with --symbol weren't found
+  17  The prompt couldn't be copied to the clipboard
+  18  Possible secrets were found in the file contents
+  19  Sensitive files were found
+  20  Line ranges were past the end of the matching files";
+
 /// Create standardized LLM prompts from your code.
 #[derive(Parser, Debug)]
-#[clap(name = "codeprompt", version = "0.1.6")]
+#[clap(name = "codeprompt", version = "0.1.6", after_long_help = EXIT_CODES_HELP)]
 struct Args {
     /// Subcommand for shell completion generation.
     #[command(subcommand)]
@@ -26,6 +57,9 @@ struct Args {
     path: Option<PathBuf>,
 
     /// Glob patterns to include.
+    ///
+    /// A pattern can end with a line range, such as `src/main.rs:100-200`, to only include these
+    /// lines of the matching files.
     #[arg(long)]
     include: Option<String>,
 
@@ -53,20 +87,96 @@ struct Args {
     #[arg(short = 'u', long, action(ArgAction::SetTrue))]
     diff_unstaged: bool,
 
+    /// Capture the git diff between a revision and the working tree (equivalent to running `git diff <rev>`).
+    #[arg(long, value_name = "REV", conflicts_with_all = ["diff_staged", "diff_unstaged", "diff_range"])]
+    diff_base: Option<String>,
+
+    /// Capture the git diff for a revision range given as A..B or A...B (equivalent to running `git diff A..B`).
+    #[arg(long, value_name = "RANGE", conflicts_with_all = ["diff_staged", "diff_unstaged"])]
+    diff_range: Option<String>,
+
+    /// Only include the files touched by the git diff. Uses the staged and unstaged changes if no diff option is passed.
+    #[arg(long, action(ArgAction::SetTrue))]
+    changed_only: bool,
+
+    /// Include the log of the N most recent commits.
+    #[arg(long, value_name = "N", conflicts_with = "log_range")]
+    log: Option<usize>,
+
+    /// Include the log of a revision range given as A..B or A...B (equivalent to running `git log A..B`).
+    #[arg(long, value_name = "RANGE")]
+    log_range: Option<String>,
+
     /// Don't display approximate token count of the genrated prompt.
     #[arg(long, action(ArgAction::SetTrue))]
     no_tokens: bool,
 
     /// Tokenizer to use for token count.
     ///
-    /// Right now cl100k is the only supported tokenizer.
+    /// Supports the cl100k, o200k, p50k, p50k_edit and r50k encodings as well as model names such
+    /// as gpt-4o or gpt-3.5.
     #[arg(short = 'c', long, default_value = "cl100k")]
     encoding: String,
 
-    /// Redirect output to file.
+    /// Maximum number of tokens for the prompt. Files that would exceed it are left out.
+    #[arg(long)]
+    max_tokens: Option<usize>,
+
+    /// Split a prompt over this many tokens into numbered chunk files, such as prompt-001.md.
+    ///
+    /// The chunks are named after the --output file when one is passed.
+    #[arg(long, value_name = "TOKENS", conflicts_with = "json")]
+    split: Option<usize>,
+
+    /// Skip files larger than this size, in bytes or with a K, M or G suffix (such as 500K).
+    #[arg(long, value_name = "SIZE", value_parser = parse_file_size)]
+    max_file_size: Option<u64>,
+
+    /// Keep lockfiles, minified files and files with a generated code header.
+    #[arg(long, action(ArgAction::SetTrue))]
+    include_generated: bool,
+
+    /// Annotate each source tree node with the token count of the files under it.
+    #[arg(long, action(ArgAction::SetTrue))]
+    tree_stats: bool,
+
+    /// Reduce files to their structural outline (signatures, type definitions and doc comments).
+    ///
+    /// Optionally takes comma delimited glob patterns of the files to outline, all files are
+    /// outlined otherwise. Supports Rust, Python, JavaScript, TypeScript and Go.
+    #[arg(long, value_name = "GLOBS", num_args = 0..=1, default_missing_value = "**")]
+    outline: Option<String>,
+
+    /// Glob patterns of the files to keep the full code for in outline mode.
+    #[arg(long, value_name = "GLOBS")]
+    full_code: Option<String>,
+
+    /// Only include the named functions, types and impl blocks, with a few lines of context.
+    ///
+    /// Takes comma delimited symbols such as `MyStruct`, `MyStruct::new` or
+    /// `src/files.rs::traverse_directory`. Supports Rust, Python, JavaScript, TypeScript and Go.
+    #[arg(long, value_name = "SYMBOLS")]
+    symbol: Option<String>,
+
+    /// Remove comments from the file contents, based on the file extension.
+    #[arg(long, action(ArgAction::SetTrue))]
+    strip_comments: bool,
+
+    /// Remove comments, trailing whitespace and runs of blank lines from the file contents.
+    #[arg(long, action(ArgAction::SetTrue))]
+    compact: bool,
+
+    /// Redirect output to file, or to stdout with `-`.
     #[arg(short = 'o', long)]
     output: Option<String>,
 
+    /// Write the prompt to stdout, the status messages are written to stderr.
+    ///
+    /// The prompt is also written to stdout when stdout isn't a terminal and no output file is
+    /// passed, so it can be piped to another command.
+    #[arg(long, action(ArgAction::SetTrue), conflicts_with_all = ["output", "json", "split"])]
+    stdout: bool,
+
     /// Turn off line numbers in source code blocks.
     #[arg(short = 'l', long, action(ArgAction::SetTrue))]
     no_line_numbers: bool,
@@ -83,10 +193,32 @@ struct Args {
     #[arg(long, action(ArgAction::SetTrue))]
     no_clipboard: bool,
 
-    /// Optional path to Handlebars template.
+    /// Clipboard backend to use. The auto backend falls back on OSC 52, wl-copy, xclip and xsel
+    /// when the native clipboard isn't available.
+    #[arg(long, value_enum, value_name = "BACKEND", default_value_t = ClipboardBackend::Auto)]
+    clipboard_backend: ClipboardBackend,
+
+    /// Optional path to Handlebars template or name of a built-in template.
     #[arg(short = 't', long)]
     template: Option<PathBuf>,
 
+    /// Format of the generated prompt.
+    ///
+    /// The xml format wraps the raw content of each file in a `<document>` tag and the source
+    /// tree, git diff, git log and issue in their own tags. The json-structured and yaml formats
+    /// emit the template data without rendering a template. The plain format renders the template
+    /// without markdown code blocks.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Markdown)]
+    format: OutputFormat,
+
+    /// User-defined template variable as KEY=VALUE. Can be repeated.
+    #[arg(long = "var", value_name = "KEY=VALUE", value_parser = parse_template_var)]
+    vars: Vec<(String, String)>,
+
+    /// Path to a TOML or JSON file with user-defined template variables.
+    #[arg(long)]
+    vars_file: Option<PathBuf>,
+
     /// Whether to render the spinner.
     #[arg(long, action(ArgAction::SetTrue))]
     no_spinner: bool,
@@ -103,9 +235,130 @@ struct Args {
     #[arg(long, action(ArgAction::SetTrue))]
     verbose: bool,
 
+    /// Replace possible secrets (API tokens, private keys, high entropy strings) in the file contents with redaction markers.
+    #[arg(long, action(ArgAction::SetTrue))]
+    redact: bool,
+
     /// Ignore all warnings (sensitive files, large token counts, template warnings).
     #[arg(long, action(ArgAction::SetTrue))]
     no_warnings: bool,
+
+    /// Never prompt for confirmation, defaults to true when stdin isn't a terminal. Sensitive
+    /// files are included with a warning and large prompts aren't copied to the clipboard.
+    #[arg(long, action(ArgAction::SetTrue))]
+    non_interactive: bool,
+
+    /// Exit with an error, before the prompt is written, when sensitive files or unredacted
+    /// secrets are found.
+    #[arg(long, action(ArgAction::SetTrue), conflicts_with = "no_warnings")]
+    fail_on_sensitive: bool,
+
+    /// Exit with the code of the first warning after the prompt is written, when there are any
+    /// warnings. The skipped files report doesn't fail the run.
+    ///
+    /// See the exit codes at the end of --help.
+    #[arg(long, action(ArgAction::SetTrue), conflicts_with = "no_warnings")]
+    fail_on_warnings: bool,
+
+    /// Don't load the global and project .codeprompt.toml config files.
+    #[arg(long, action(ArgAction::SetTrue))]
+    no_config: bool,
+
+    /// Name of a config file profile to apply on top of the config file defaults.
+    #[arg(short = 'p', long, value_name = "NAME", conflicts_with = "no_config")]
+    profile: Option<String>,
+}
+
+impl Args {
+    /// Applies the config file defaults to the options that weren't passed on the command line.
+    ///
+    /// ### Arguments
+    ///
+    /// - `defaults`: The merged config file defaults.
+    /// - `matches`: The parsed command line arguments.
+    ///
+    fn apply_config(&mut self, defaults: &Defaults, matches: &ArgMatches) {
+        let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+
+        macro_rules! apply_flag {
+            ($field:ident) => {
+                if let (false, Some(value)) = (from_cli(stringify!($field)), defaults.$field) {
+                    self.$field = value;
+                }
+            };
+        }
+        macro_rules! apply_value {
+            ($field:ident) => {
+                if let (false, Some(value)) = (from_cli(stringify!($field)), &defaults.$field) {
+                    self.$field = value.clone().into();
+                }
+            };
+        }
+
+        if !from_cli("include") {
+            if let Some(patterns) = &defaults.include {
+                self.include = Some(patterns.join(","));
+            }
+        }
+        if !from_cli("exclude") {
+            if let Some(patterns) = &defaults.exclude {
+                self.exclude = Some(patterns.join(","));
+            }
+        }
+
+        if !from_cli("outline") {
+            if let Some(patterns) = &defaults.outline {
+                self.outline = Some(patterns.join(","));
+            }
+        }
+        if !from_cli("full_code") {
+            if let Some(patterns) = &defaults.full_code {
+                self.full_code = Some(patterns.join(","));
+            }
+        }
+
+        // The config diff options only apply if no diff option was passed.
+        let diff_from_cli = ["diff_staged", "diff_unstaged", "diff_base", "diff_range"]
+            .into_iter()
+            .any(from_cli);
+        if !diff_from_cli {
+            apply_flag!(diff_staged);
+            apply_flag!(diff_unstaged);
+            apply_value!(diff_base);
+            apply_value!(diff_range);
+        }
+        apply_flag!(changed_only);
+
+        apply_flag!(exclude_priority);
+        apply_flag!(exclude_from_tree);
+        apply_flag!(gitignore);
+        apply_flag!(no_tokens);
+        apply_value!(encoding);
+        apply_value!(max_tokens);
+        apply_value!(split);
+        apply_value!(max_file_size);
+        apply_flag!(include_generated);
+        apply_flag!(tree_stats);
+        apply_flag!(strip_comments);
+        apply_flag!(compact);
+        apply_flag!(no_line_numbers);
+        apply_flag!(no_codeblock);
+        // The --relative-paths flag renders absolute paths, so the config value is inverted.
+        if let (false, Some(value)) = (from_cli("relative_paths"), defaults.absolute_paths) {
+            self.relative_paths = !value;
+        }
+        apply_flag!(no_clipboard);
+        apply_value!(clipboard_backend);
+        apply_value!(template);
+        apply_value!(format);
+        apply_value!(vars_file);
+        apply_flag!(no_spinner);
+        apply_flag!(redact);
+        apply_flag!(no_warnings);
+        apply_flag!(non_interactive);
+        apply_flag!(fail_on_sensitive);
+        apply_flag!(fail_on_warnings);
+    }
 }
 
 #[derive(Subcommand, Debug)]
@@ -115,12 +368,38 @@ enum SubCommand {
         #[clap(value_enum)]
         shell: Shell,
     },
+    #[command(about = "Work with the built-in templates.")]
+    Templates {
+        #[command(subcommand)]
+        action: TemplatesAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum TemplatesAction {
+    #[command(about = "List the built-in templates.")]
+    List,
 }
 
 fn print_completions<G: Generator>(gen: G, cmd: &mut Command) {
     generate(gen, cmd, cmd.get_name().to_owned(), &mut std::io::stdout());
 }
 
+fn print_templates() {
+    let width = BUILTIN_TEMPLATES
+        .iter()
+        .map(|template| template.name.len())
+        .max()
+        .unwrap_or(0);
+    for template in BUILTIN_TEMPLATES {
+        println!(
+            "{}  {}",
+            format!("{:width$}", template.name, width = width).bold(),
+            template.description
+        );
+    }
+}
+
 /// Main entry point for the codeprompt application.
 ///
 /// ### Returns
@@ -128,7 +407,8 @@ fn print_completions<G: Generator>(gen: G, cmd: &mut Command) {
 /// - `Result<(), Error>`: Ok(()) on successful execution, or an Error if any step fails.
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    let args = Args::parse();
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
 
     logging::setup(args.verbose);
 
@@ -138,8 +418,14 @@ async fn main() -> Result<(), Error> {
             print_completions(*shell, &mut cmd);
             return Ok(());
         }
+        Some(SubCommand::Templates {
+            action: TemplatesAction::List,
+        }) => {
+            print_templates();
+            return Ok(());
+        }
         None => {
-            if let Some(project_root) = args.path {
+            if let Some(project_root) = args.path.clone() {
                 project_root
             } else {
                 eprintln!(
@@ -147,7 +433,7 @@ async fn main() -> Result<(), Error> {
                     "[".bold().white(),
                     "!".bold().red(),
                     "]".bold().white(),
-                    "Error: PATH argument is required when not using the completion subcommand."
+                    "Error: PATH argument is required when not using a subcommand."
                         .bold()
                         .red()
                 );
@@ -156,16 +442,62 @@ async fn main() -> Result<(), Error> {
         }
     };
 
-    let validation_config = ValidationConfig::new(
+    let defaults = if args.no_config {
+        Defaults::default()
+    } else {
+        Config::load(&project_root)?.resolve(args.profile.as_deref())?
+    };
+    args.apply_config(&defaults, &matches);
+
+    // A pipe or file on stdout can't show the spinner, and the prompt is written to it instead of
+    // the clipboard.
+    let stdout_is_terminal = std::io::stdout().is_terminal();
+    if !stdout_is_terminal {
+        args.no_spinner = true;
+        args.no_clipboard = true;
+    }
+    // The confirmation prompts would block on a pipe or in CI.
+    if !std::io::stdin().is_terminal() {
+        args.non_interactive = true;
+    }
+    let to_stdout = args.stdout
+        || args.output.as_deref() == Some("-")
+        || (!stdout_is_terminal && args.output.is_none() && !args.json);
+    // The chunks are always written to files.
+    if args.split.is_some() && (args.stdout || args.output.as_deref() == Some("-")) {
+        return Err(anyhow!(
+            "--split writes the chunks to files and can't be used with --stdout or -o -"
+        ));
+    }
+
+    let diff_source = DiffSource::from_flags(
         args.diff_staged,
         args.diff_unstaged,
+        args.diff_base.as_deref(),
+        args.diff_range.as_deref(),
+    )
+    .or(args.changed_only.then_some(DiffSource::StagedAndUnstaged));
+
+    let log_source = args
+        .log
+        .map(LogSource::Recent)
+        .or(args.log_range.clone().map(LogSource::Range));
+
+    // The template is loaded first since the validation checks which variables it renders.
+    let (template, template_name) = get_template(&args.template, args.format)?;
+    let handlebars = setup_handlebars_registry(&template, template_name, args.format)?;
+
+    let validation_config = ValidationConfig::new(
+        &diff_source,
+        &log_source,
         args.issue,
-        &args.template,
+        &template,
+        args.format,
     );
 
     if let Err(error) = validation_config.validate_git_repo(&project_root) {
         eprintln!("{}", error.format());
-        std::process::exit(1);
+        std::process::exit(error.exit_code());
     }
 
     // Get other warnings
@@ -175,71 +507,66 @@ async fn main() -> Result<(), Error> {
         Vec::new()
     };
 
-    let (template, template_name) = get_template(&args.template)?;
-    let handlebars = setup_handlebars_registry(&template, template_name)?;
-
-    let include_patterns = parse_comma_delim_patterns(&args.include);
-    let exclude_patterns = parse_comma_delim_patterns(&args.exclude);
-
-    let sensitive_files = check_sensitive_files(
-        &project_root,
-        &include_patterns,
-        &exclude_patterns,
-        args.exclude_priority,
-        args.relative_paths,
-        args.gitignore,
-    )?;
-
-    if !args.no_warnings
-        && !sensitive_files.is_empty()
-        && !prompt_for_sensitive_files(&sensitive_files)
+    // The tokenizer is needed for the token count, the token budget and the tree stats.
+    let bpe = if !args.no_tokens
+        || args.max_tokens.is_some()
+        || args.tree_stats
+        || args.split.is_some()
     {
-        eprintln!(
-            "\n{}{}{} {}",
-            "[".bold().white(),
-            "!".bold().red(),
-            "]".bold().white(),
-            "Operation cancelled by user".red()
-        );
-        std::process::exit(1);
-    }
-
-    let spinner = if !args.no_spinner {
-        Some(setup_spinner("Building directory tree..."))
+        Some(tokenizer_init(&args.encoding)?)
     } else {
         None
     };
 
-    let tree_data = traverse_directory(
+    let (include_patterns, line_ranges) =
+        split_line_ranges(parse_comma_delim_patterns(&args.include))?;
+    let exclude_patterns = parse_comma_delim_patterns(&args.exclude);
+    let outline_patterns = args
+        .outline
+        .as_ref()
+        .map(|patterns| parse_comma_delim_patterns(&Some(patterns.to_owned())));
+    let full_code_patterns = parse_comma_delim_patterns(&args.full_code);
+    let symbol_queries = parse_comma_delim_patterns(&args.symbol)
+        .iter()
+        .map(|spec| SymbolQuery::parse(spec))
+        .collect::<Result<Vec<_>>>()?;
+
+    let file_patterns = defaults.file_patterns();
+
+    let sensitive_files = check_sensitive_files(
         &project_root,
         &include_patterns,
         &exclude_patterns,
         args.exclude_priority,
-        args.no_line_numbers,
         args.relative_paths,
-        args.exclude_from_tree,
-        args.no_codeblock,
         args.gitignore,
-    );
+        &file_patterns,
+    )?;
 
-    let (tree, files) = match tree_data {
-        Ok(result) => result,
-        Err(e) => {
-            if let Some(s) = &spinner {
-                s.finish_with_message("Failed!".red().to_string());
-            }
+    if !args.no_warnings && !sensitive_files.is_empty() {
+        if args.fail_on_sensitive {
+            fail_with(&ValidationWarning::SensitiveFiles(sensitive_files));
+        } else if args.non_interactive {
+            warnings.push(ValidationWarning::SensitiveFiles(sensitive_files));
+        } else if !prompt_for_sensitive_files(&sensitive_files) {
             eprintln!(
                 "\n{}{}{} {}",
                 "[".bold().white(),
                 "!".bold().red(),
                 "]".bold().white(),
-                format!("Failed to traverse directories: {}", e).red()
+                "Operation cancelled by user".red()
             );
-            std::process::exit(1);
+            std::process::exit(EXIT_FAILURE);
         }
+    }
+
+    let spinner = if !args.no_spinner {
+        Some(setup_spinner("Generating prompt data..."))
+    } else {
+        None
     };
 
-    let repo = if args.diff_unstaged || args.diff_staged || args.issue.is_some() {
+    let repo = if diff_source.is_some() || log_source.is_some() || args.issue.is_some() {
         Some(
             Repository::open(&project_root)
                 .context("Failed to open the repository. Check your current working directory.")?,
@@ -248,53 +575,68 @@ async fn main() -> Result<(), Error> {
         None
     };
 
-    let git_diff_str = if args.diff_unstaged || args.diff_staged {
+    let git_diff_str = if let Some(diff_source) = &diff_source {
         if let Some(s) = &spinner {
             s.set_message("Generating git diff...");
         }
-        match (args.diff_staged, args.diff_unstaged) {
-            (true, true) => repo.as_ref().map_or(
-                Err(Error::msg("Used git diff flag but failed to open the repository. Check your current working directory.")),
-                |repo| git_diff(repo, 2))?,
-            (true, false) => repo.as_ref().map_or(
-                Err(Error::msg("Used git diff flag but failed to open the repository. Check your current working directory.")),
-                |repo| git_diff(repo, 0))?,
-            (false, true) => repo.as_ref().map_or(
-                Err(Error::msg("Used git diff flag but failed to open the repository. Check your current working directory.")),
-                |repo| git_diff(repo, 1))?,
-            (_, _) => return Err(Error::msg("Error parsing git diff arguments.")),
-        }
+        repo.as_ref().map_or(
+            Err(Error::msg("Used git diff flag but failed to open the repository. Check your current working directory.")),
+            |repo| git_diff(repo, diff_source))?
     } else {
         String::new()
     };
 
-    if let Some(s) = &spinner {
-        s.finish_with_message("Done!".green().to_string());
-    }
+    let changed_files = match (&diff_source, &repo) {
+        (Some(diff_source), Some(repo)) if args.changed_only => Some(
+            git_changed_files(repo, diff_source)?
+                .into_iter()
+                .collect::<HashSet<_>>(),
+        ),
+        _ => None,
+    };
+
+    let git_log_entries = if let Some(log_source) = &log_source {
+        if let Some(s) = &spinner {
+            s.set_message("Generating git log...");
+        }
+        repo.as_ref().map_or(
+            Err(Error::msg("Used git log flag but failed to open the repository. Check your current working directory.")),
+            |repo| git_log(repo, log_source))?
+    } else {
+        Vec::new()
+    };
 
     let mut json_data = json!({
         "absolute_code_path": basename(&project_root),
-        "source_tree": tree,
-        "files": files,
+        "source_tree": "",
+        "files": [],
         "git_diff": git_diff_str,
+        "git_log": git_log_entries,
     });
 
+    // Merge the user-defined variables. The built-in variables can't be overridden.
+    let template_vars = merge_template_vars(&defaults.vars, args.vars_file.as_deref(), &args.vars)?;
+    if let Some(data) = json_data.as_object_mut() {
+        for (key, value) in template_vars {
+            data.entry(key).or_insert(value);
+        }
+    }
+
     if let Some(issue_number) = args.issue {
         if let Some(s) = &spinner {
             s.set_message(format!("Fetching Github issue #{}...", issue_number));
         }
         let (owner, repo_name) = repo.as_ref().map_or(
             Err(Error::msg("Used issue flag but failed to open the repository. Check your current working directory.")),
-            |repo| get_repo_info(repo))?;
+            get_repo_info)?;
         match fetch_github_issue(&owner, &repo_name, issue_number).await {
             Ok(issue) => {
                 json_data["github_issue"] = serde_json::to_value(issue)?;
                 if let Some(s) = &spinner {
-                    s.finish_with_message(
-                        format!("Github issue #{} fetched successfully!", issue_number)
-                            .green()
-                            .to_string(),
-                    );
+                    s.set_message(format!(
+                        "Github issue #{} fetched successfully!",
+                        issue_number
+                    ));
                 }
             }
             Err(e) => {
@@ -310,25 +652,164 @@ async fn main() -> Result<(), Error> {
                     "[".bold().white(),
                     "!".bold().red(),
                     "]".bold().white(),
-                    format!("Failed to retrieve Github repo.").red()
+                    "Failed to retrieve Github repo.".red()
                 );
                 std::process::exit(1);
             }
         }
     }
 
-    let rendered_output = render_template(&handlebars, template_name, &json_data)?;
-
-    let tokens = if !args.no_tokens {
-        let bpe = tokenizer_init(&args.encoding);
-        bpe.encode_with_special_tokens(&rendered_output).len()
+    // The token budget for the source tree and files is what is left after rendering the
+    // template without them. The template text around the source tree is measured by rendering
+    // it with a placeholder tree.
+    let count_tokens = |data: &serde_json::Value| -> Result<usize> {
+        let rendered = render_output(&handlebars, template_name, data, args.format)?;
+        Ok(bpe
+            .as_ref()
+            .map_or(0, |bpe| bpe.encode_with_special_tokens(&rendered).len()))
+    };
+    let file_budget = match (&bpe, args.max_tokens) {
+        (Some(bpe), Some(max_tokens)) => {
+            let mut data = json_data.clone();
+            data["source_tree"] = TREE_PLACEHOLDER.into();
+            let overhead = count_tokens(&data)?
+                .saturating_sub(bpe.encode_with_special_tokens(TREE_PLACEHOLDER).len());
+            Some(max_tokens.saturating_sub(overhead))
+        }
+        _ => None,
+    };
+    // The cost of a file is measured by rendering the template with only that file, so it
+    // includes the text around the file and the escaping of the output format. It is only needed
+    // for the token budget and the chunks.
+    let empty_files_tokens = if file_budget.is_some() || args.split.is_some() {
+        count_tokens(&json!({ "files": [] }))?
     } else {
         0
     };
+    let file_cost = |file: &serde_json::Value| -> Result<usize> {
+        let tokens = count_tokens(&json!({ "files": [file] })).with_context(|| {
+            format!(
+                "Failed to count the tokens of {}",
+                file["path"].as_str().unwrap_or_default()
+            )
+        })?;
+        Ok(tokens.saturating_sub(empty_files_tokens))
+    };
+
+    if let Some(s) = &spinner {
+        s.set_message("Building directory tree...");
+    }
+
+    let traversal_config = TraversalConfig {
+        include: &include_patterns,
+        exclude: &exclude_patterns,
+        exclude_priority: args.exclude_priority,
+        no_line_numbers: args.no_line_numbers || !args.format.uses_line_numbers(),
+        relative_paths: args.relative_paths,
+        exclude_from_tree: args.exclude_from_tree,
+        // Only the markdown format wraps the file contents in code blocks.
+        no_codeblock: args.no_codeblock || !args.format.uses_codeblocks(),
+        gitignore: args.gitignore,
+        ignore_list: &file_patterns.ignore,
+        include_paths: changed_files.as_ref(),
+        tokenizer: bpe.as_ref(),
+        max_tokens: file_budget,
+        file_cost: file_budget.map(|_| FileCost(&file_cost)),
+        tree_stats: args.tree_stats,
+        scan_secrets: args.redact || !args.no_warnings,
+        redact: args.redact,
+        max_file_size: args.max_file_size,
+        include_generated: args.include_generated,
+        outline: outline_patterns.as_deref(),
+        full_code: &full_code_patterns,
+        line_ranges: &line_ranges,
+        symbols: &symbol_queries,
+        strip_comments: args.strip_comments || args.compact,
+        compact: args.compact,
+    };
+
+    let traversal = match traverse_directory(&project_root, &traversal_config) {
+        Ok(result) => result,
+        Err(e) => {
+            if let Some(s) = &spinner {
+                s.finish_with_message("Failed!".red().to_string());
+            }
+            eprintln!(
+                "\n{}{}{} {}",
+                "[".bold().white(),
+                "!".bold().red(),
+                "]".bold().white(),
+                format!("Failed to traverse directories: {}", e).red()
+            );
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(s) = &spinner {
+        s.finish_with_message("Done!".green().to_string());
+    }
+
+    if let Some(max_tokens) = args.max_tokens {
+        if !traversal.omitted.is_empty() {
+            warnings.push(ValidationWarning::FilesOmitted {
+                max_tokens,
+                omitted: traversal.omitted.clone(),
+            });
+        }
+    }
+
+    if !traversal.skipped.is_empty() {
+        warnings.push(ValidationWarning::FilesSkipped(traversal.skipped.clone()));
+    }
+
+    if !traversal.out_of_range.is_empty() {
+        warnings.push(ValidationWarning::LineRangesOutOfBounds(
+            traversal.out_of_range.clone(),
+        ));
+    }
+
+    if !traversal.missing_symbols.is_empty() {
+        warnings.push(ValidationWarning::SymbolsNotFound(
+            traversal.missing_symbols.clone(),
+        ));
+    }
+
+    if !traversal.secrets.is_empty() {
+        let warning = ValidationWarning::SecretsDetected {
+            redacted: args.redact,
+            findings: traversal.secrets.clone(),
+        };
+        if args.fail_on_sensitive && warning.is_sensitive() {
+            fail_with(&warning);
+        }
+        warnings.push(warning);
+    }
+
+    let files = traversal.files;
+    json_data["source_tree"] = traversal.tree.into();
+    json_data["files"] = serde_json::to_value(&files)?;
+
+    // The structured formats don't render the template.
+    if !args.no_warnings && !args.format.is_structured() {
+        let missing_vars = find_missing_variables(&template, &json_data)?;
+        if !missing_vars.is_empty() {
+            warnings.push(ValidationWarning::MissingTemplateVariables(missing_vars));
+        }
+    }
+
+    let rendered_output = render_output(&handlebars, template_name, &json_data, args.format)?;
+
+    let tokens = match (&bpe, args.no_tokens) {
+        (Some(bpe), false) => bpe.encode_with_special_tokens(&rendered_output).len(),
+        _ => 0,
+    };
 
     // Add token count warning if needed
     if !args.no_clipboard && !args.no_warnings {
-        if let Some(warning) = validate_token_count(tokens) {
+        let threshold = defaults
+            .token_warning_threshold
+            .unwrap_or(TOKEN_WARNING_THRESHOLD);
+        if let Some(warning) = validate_token_count(tokens, threshold) {
             warnings.push(warning);
         }
     }
@@ -338,37 +819,87 @@ async fn main() -> Result<(), Error> {
         .filter_map(|f| f.get("path").and_then(|p| p.as_str()).map(|s| s.to_owned()))
         .collect();
 
+    if !args.json && !args.no_tokens {
+        eprintln!(
+            "\n{}{}{} Token count: {}",
+            "[".bold().white(),
+            "i".bold().blue(),
+            "]".bold().white(),
+            tokens.to_string().bold().yellow()
+        );
+    }
+
+    // The warnings are reported before the prompt is written so that every output fails the same
+    // way with --fail-on-warnings.
+    let mut failure = report_warnings(&warnings, &args);
+
     if args.json {
+        let file_stats: Vec<serde_json::Value> = files
+            .iter()
+            .map(|f| {
+                json!({
+                    "path": f["path"],
+                    "token_count": f["token_count"],
+                    "line_count": f["line_count"],
+                    "byte_size": f["byte_size"],
+                })
+            })
+            .collect();
         let json_output = json!({
             "prompt": rendered_output,
             "directory_name": basename(&project_root),
             "token_count": tokens,
             "files": paths,
+            "file_stats": file_stats,
+            "omitted_files": traversal.omitted,
+            "skipped_files": traversal
+                .skipped
+                .iter()
+                .map(|file| json!({ "path": file.path, "reason": file.reason.to_string() }))
+                .collect::<Vec<_>>(),
         });
         println!("{}", serde_json::to_string_pretty(&json_output)?);
-        return Ok(());
-    } else {
-        if !args.no_tokens {
-            println!(
-                "\n{}{}{} Token count: {}",
-                "[".bold().white(),
-                "i".bold().blue(),
-                "]".bold().white(),
-                tokens.to_string().bold().yellow()
-            );
+        if let Some(code) = failure {
+            std::process::exit(code);
         }
+        return Ok(());
     }
 
-    let should_copy_to_clipboard = if args.no_clipboard {
+    // Split the prompt into chunks when it is over the per-chunk token limit.
+    let chunks = match (args.split, &bpe) {
+        (Some(max_tokens), Some(bpe))
+            if bpe.encode_with_special_tokens(&rendered_output).len() > max_tokens =>
+        {
+            split_prompt(
+                &handlebars,
+                template_name,
+                &json_data,
+                args.format,
+                bpe,
+                max_tokens,
+                &file_cost,
+            )?
+        }
+        _ => Vec::new(),
+    };
+
+    // The chunks are written to files instead of being copied to the clipboard.
+    let should_copy_to_clipboard = if args.no_clipboard || !chunks.is_empty() {
         false
     } else if !args.no_tokens {
-        validate_clipboard_copy(tokens, args.no_warnings)
+        let threshold = defaults
+            .clipboard_token_threshold
+            .unwrap_or(CLIPBOARD_TOKEN_THRESHOLD);
+        validate_clipboard_copy(tokens, threshold, args.no_warnings, !args.non_interactive)
     } else {
         true
     };
 
     if should_copy_to_clipboard {
-        copy_to_clipboard(&rendered_output)?;
+        if let Err(e) = copy_prompt(&rendered_output, args.clipboard_backend) {
+            let warning = ValidationWarning::ClipboardFailed(format!("{:#}", e));
+            failure = failure.or(report_warnings(&[warning], &args));
+        }
     } else if !args.no_clipboard {
         eprintln!(
             "{}{}{} {}",
@@ -379,8 +910,10 @@ async fn main() -> Result<(), Error> {
         );
     }
 
-    if let Some(output_path) = &args.output {
-        if let Err(e) = write_output_file(output_path, &rendered_output) {
+    // A file that couldn't be written fails the run, even if warnings set another exit code.
+    for (idx, chunk) in chunks.iter().enumerate() {
+        let chunk_path = chunk_path(args.output.as_deref(), args.format, idx + 1);
+        if let Err(e) = write_output_file(&chunk_path, chunk) {
             eprintln!(
                 "{}{}{} {}",
                 "[".bold().white(),
@@ -388,40 +921,97 @@ async fn main() -> Result<(), Error> {
                 "]".bold().white(),
                 format!("Output error: {}", e).red()
             );
+            failure = Some(EXIT_FAILURE);
         }
     }
 
-    // Print warnings if needed
-    if !args.no_warnings && !warnings.is_empty() {
-        for warning in warnings {
-            eprintln!("{}", warning.format());
+    if !chunks.is_empty() {
+        // The chunks were written to files.
+    } else if to_stdout {
+        write_stdout(&rendered_output)?;
+    } else if let Some(output_path) = &args.output {
+        if let Err(e) = write_output_file(output_path, &rendered_output) {
+            eprintln!(
+                "{}{}{} {}",
+                "[".bold().white(),
+                "!".bold().red(),
+                "]".bold().white(),
+                format!("Output error: {}", e).red()
+            );
+            failure = Some(EXIT_FAILURE);
         }
     }
 
+    if let Some(code) = failure {
+        std::process::exit(code);
+    }
+
     Ok(())
 }
 
-/// Copies the output to the system clipboard.
+/// Prints the warnings to stderr.
+///
+/// ### Arguments
+///
+/// - `warnings`: The warnings to print.
+/// - `args`: The command line arguments.
+///
+/// ### Returns
+///
+/// - `Option<i32>`: The exit code of the first warning with --fail-on-warnings, or None if the
+///   run shouldn't fail.
+///
+fn report_warnings(warnings: &[ValidationWarning], args: &Args) -> Option<i32> {
+    if args.no_warnings {
+        return None;
+    }
+    for warning in warnings {
+        eprintln!("{}", warning.format());
+    }
+    if !args.fail_on_warnings {
+        return None;
+    }
+    warnings.iter().find_map(ValidationWarning::exit_code)
+}
+
+/// Placeholder source tree used to measure the tokens the template adds around it.
+const TREE_PLACEHOLDER: &str = "placeholder";
+
+/// Prints a warning as an error and exits with the exit code of the warning.
+///
+/// ### Arguments
+///
+/// - `warning`: The warning that fails the run.
+///
+fn fail_with(warning: &ValidationWarning) -> ! {
+    eprintln!("{}", warning.format());
+    std::process::exit(warning.exit_code().unwrap_or(EXIT_FAILURE));
+}
+
+/// Copies the output to the clipboard.
 ///
 /// ### Arguments
 ///
 /// - `content`: The content to copy to the clipboard.
+/// - `backend`: The clipboard backend to use.
 ///
 /// ### Returns
 ///
 /// - `Result<(), anyhow::Error>`: Unit tuple on success or an anyhow error.
 ///
-fn copy_to_clipboard(content: &str) -> Result<(), Error> {
-    let mut clipboard = Clipboard::new().expect("Failed to initialize clipboard.");
-    clipboard
-        .set_text(content.to_owned())
-        .context("Failed to copy output to clipboard.")?;
-    println!(
+fn copy_prompt(content: &str, backend: ClipboardBackend) -> Result<(), Error> {
+    let message = match copy_to_clipboard(content, backend)? {
+        ClipboardBackend::Native => "Prompt successfully copied to clipboard!".to_owned(),
+        // The terminal doesn't confirm the OSC 52 escape sequence was handled.
+        ClipboardBackend::Osc52 => "Prompt sent to the terminal clipboard (OSC 52)".to_owned(),
+        backend => format!("Prompt successfully copied to clipboard with {}!", backend),
+    };
+    eprintln!(
         "{}{}{} {}",
         "[".bold().white(),
         "✓".bold().green(),
         "]".bold().white(),
-        "Prompt successfully copied to clipboard!".green()
+        message.green()
     );
     Ok(())
 }
@@ -439,7 +1029,11 @@ fn copy_to_clipboard(content: &str) -> Result<(), Error> {
 ///
 fn write_output_file(path: &str, content: &str) -> Result<(), Error> {
     let path_obj = std::path::Path::new(path);
-    if let Some(parent) = path_obj.parent() {
+    // The parent of a bare file name is empty, which stands for the current directory.
+    if let Some(parent) = path_obj
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
         if !parent.exists() {
             return Err(anyhow!(
                 "Output directory '{}' does not exist",
@@ -455,7 +1049,7 @@ fn write_output_file(path: &str, content: &str) -> Result<(), Error> {
     write!(writer, "{}", content)
         .with_context(|| format!("Failed to write to output file: {}", path))?;
 
-    println!(
+    eprintln!(
         "{}{}{} {}",
         "[".bold().white(),
         "✓".bold().green(),
@@ -464,3 +1058,82 @@ fn write_output_file(path: &str, content: &str) -> Result<(), Error> {
     );
     Ok(())
 }
+
+/// Writes the output to stdout.
+///
+/// ### Arguments
+///
+/// - `content`: The content to write to stdout.
+///
+/// ### Returns
+///
+/// - `Result<(), anyhow::Error>`: Unit tuple on success or an anyhow error. A closed pipe, such
+///   as `codeprompt . | head`, isn't an error.
+///
+fn write_stdout(content: &str) -> Result<(), Error> {
+    let mut stdout = std::io::stdout().lock();
+    match writeln!(stdout, "{}", content).and_then(|_| stdout.flush()) {
+        Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => {
+            Err(e).context("Failed to write the prompt to stdout")
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses the command line and applies the config defaults to it.
+    fn args_with_config(argv: &[&str], config: &str) -> Args {
+        let matches = Args::command().get_matches_from(argv);
+        let mut args = Args::from_arg_matches(&matches).unwrap();
+        let defaults = toml::from_str::<Config>(config)
+            .unwrap()
+            .resolve(None)
+            .unwrap();
+        args.apply_config(&defaults, &matches);
+        args
+    }
+
+    #[test]
+    fn command_line_overrides_config() {
+        let config = "[defaults]\nmax_tokens = 1000\nencoding = \"o200k\"\nno_clipboard = true\n";
+
+        let args = args_with_config(&["codeprompt", ".", "--max-tokens", "50"], config);
+
+        assert_eq!(args.max_tokens, Some(50));
+        assert_eq!(args.encoding, "o200k");
+        assert!(args.no_clipboard);
+    }
+
+    #[test]
+    fn config_diff_options_only_apply_without_diff_options() {
+        let config = "[defaults]\ndiff_staged = true\n";
+
+        let args = args_with_config(&["codeprompt", ".", "--diff-base", "main"], config);
+        assert!(!args.diff_staged);
+        assert_eq!(args.diff_base.as_deref(), Some("main"));
+
+        let args = args_with_config(&["codeprompt", "."], config);
+        assert!(args.diff_staged);
+    }
+
+    #[test]
+    fn config_absolute_paths_renders_absolute_paths() {
+        let args = args_with_config(&["codeprompt", "."], "");
+        assert!(args.relative_paths);
+
+        let args = args_with_config(&["codeprompt", "."], "[defaults]\nabsolute_paths = true\n");
+        assert!(!args.relative_paths);
+
+        let args = args_with_config(&["codeprompt", "."], "[defaults]\nabsolute_paths = false\n");
+        assert!(args.relative_paths);
+
+        let args = args_with_config(
+            &["codeprompt", ".", "--relative-paths"],
+            "[defaults]\nabsolute_paths = false\n",
+        );
+        assert!(!args.relative_paths);
+    }
+}
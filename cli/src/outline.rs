@@ -0,0 +1,414 @@
+//! # Outline Module
+//!
+//! Handles the structural outline of source files. The file is parsed with tree-sitter and the
+//! function bodies are elided, leaving the signatures, type definitions, class and method
+//! headers and doc comments. The kept lines keep their original line numbers.
+
+use crate::files::SourceLine;
+use std::borrow::Cow;
+use tree_sitter::{Language, Node, Parser};
+
+/// Marker inserted in place of an elided function body.
+pub(crate) const ELISION_MARKER: &str = "...";
+
+/// A language supported by the outline mode and the symbol extraction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum OutlineLanguage {
+    Rust,
+    Python,
+    JavaScript,
+    TypeScript,
+    Tsx,
+    Go,
+}
+
+impl OutlineLanguage {
+    /// Looks up the language from a file extension.
+    pub(crate) fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "rs" => Some(Self::Rust),
+            "py" | "pyi" => Some(Self::Python),
+            "js" | "jsx" | "mjs" | "cjs" => Some(Self::JavaScript),
+            "ts" | "mts" | "cts" => Some(Self::TypeScript),
+            "tsx" => Some(Self::Tsx),
+            "go" => Some(Self::Go),
+            _ => None,
+        }
+    }
+
+    /// The tree-sitter grammar of the language.
+    pub(crate) fn grammar(self) -> Language {
+        match self {
+            Self::Rust => tree_sitter_rust::LANGUAGE.into(),
+            Self::Python => tree_sitter_python::LANGUAGE.into(),
+            Self::JavaScript => tree_sitter_javascript::LANGUAGE.into(),
+            Self::TypeScript => tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+            Self::Tsx => tree_sitter_typescript::LANGUAGE_TSX.into(),
+            Self::Go => tree_sitter_go::LANGUAGE.into(),
+        }
+    }
+
+    /// The node kinds whose `body` field is elided.
+    fn function_kinds(self) -> &'static [&'static str] {
+        match self {
+            Self::Rust => &["function_item"],
+            Self::Python => &["function_definition"],
+            Self::JavaScript | Self::TypeScript | Self::Tsx => &[
+                "function_declaration",
+                "function_expression",
+                "generator_function_declaration",
+                "generator_function",
+                "method_definition",
+                "arrow_function",
+            ],
+            Self::Go => &["function_declaration", "method_declaration", "func_literal"],
+        }
+    }
+}
+
+/// Builds the structural outline of a source file.
+///
+/// ### Arguments
+///
+/// - `content`: The file content.
+/// - `extension`: The file extension, used to pick the language.
+///
+/// ### Returns
+///
+/// - `Option<Vec<SourceLine>>`: The outline lines numbered with their original line number, or
+///   None if the language isn't supported or the file can't be parsed.
+///
+pub fn outline<'a>(content: &'a str, extension: &str) -> Option<Vec<SourceLine<'a>>> {
+    let language = OutlineLanguage::from_extension(extension)?;
+    let mut parser = Parser::new();
+    parser.set_language(&language.grammar()).ok()?;
+    let tree = parser.parse(content, None)?;
+
+    // Collect the 0-based, inclusive row ranges of the elided bodies.
+    let mut elided = Vec::new();
+    collect_elided_rows(tree.root_node(), language, &mut elided);
+    elided.sort_unstable();
+
+    let mut lines = Vec::new();
+    let mut ranges = elided.into_iter().peekable();
+    for (row, line) in content.lines().enumerate() {
+        // Skip the ranges that were nested in an earlier range.
+        while ranges.peek().is_some_and(|(_, end)| *end < row) {
+            ranges.next();
+        }
+        match ranges.peek() {
+            Some((start, _)) if *start == row => {
+                let indent = &line[..line.len() - line.trim_start().len()];
+                lines.push(SourceLine {
+                    number: None,
+                    text: Cow::Owned(format!("{}{}", indent, ELISION_MARKER)),
+                });
+            }
+            Some((start, _)) if *start < row => {}
+            _ => lines.push(SourceLine {
+                number: Some(row + 1),
+                text: Cow::Borrowed(line),
+            }),
+        }
+    }
+
+    Some(lines)
+}
+
+/// Collects the rows of the function bodies to elide.
+///
+/// ### Arguments
+///
+/// - `node`: The node to search.
+/// - `language`: The language of the file.
+/// - `elided`: The 0-based, inclusive row ranges to elide.
+///
+fn collect_elided_rows(node: Node, language: OutlineLanguage, elided: &mut Vec<(usize, usize)>) {
+    if language.function_kinds().contains(&node.kind()) {
+        if let Some(body) = node.child_by_field_name("body") {
+            if let Some(range) = body_rows(body, language) {
+                elided.push(range);
+            }
+            return;
+        }
+    }
+
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        collect_elided_rows(child, language, elided);
+    }
+}
+
+/// Computes the rows of a function body to elide.
+///
+/// For the languages using braces the lines between the opening and closing brace are elided.
+/// For Python the block is elided after its docstring.
+///
+/// ### Arguments
+///
+/// - `body`: The body node.
+/// - `language`: The language of the file.
+///
+/// ### Returns
+///
+/// - `Option<(usize, usize)>`: The 0-based, inclusive row range, or None if there is nothing to
+///   elide.
+///
+fn body_rows(body: Node, language: OutlineLanguage) -> Option<(usize, usize)> {
+    let start_row = body.start_position().row;
+    let end_row = body.end_position().row;
+
+    match language {
+        OutlineLanguage::Python => {
+            // A body on the same line as the signature is kept.
+            if body.parent().map(|parent| parent.start_position().row) == Some(start_row) {
+                return None;
+            }
+            let start = match docstring(body) {
+                Some(docstring) => docstring.end_position().row + 1,
+                None => start_row,
+            };
+            (start <= end_row).then_some((start, end_row))
+        }
+        _ => {
+            // Arrow functions can have an expression body, only statement blocks are elided.
+            let is_block = matches!(body.kind(), "block" | "statement_block");
+            (is_block && end_row > start_row + 1).then_some((start_row + 1, end_row - 1))
+        }
+    }
+}
+
+/// Finds the docstring of a Python function body.
+///
+/// ### Arguments
+///
+/// - `body`: The block node of the function.
+///
+/// ### Returns
+///
+/// - `Option<Node>`: The docstring statement, if the first statement of the block is a string.
+///
+fn docstring(body: Node) -> Option<Node> {
+    let first = body.named_child(0)?;
+    let is_docstring = first.kind() == "expression_statement"
+        && first
+            .named_child(0)
+            .is_some_and(|child| child.kind() == "string");
+    is_docstring.then_some(first)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Renders the outline lines prefixed with their line number, or `-` for the elision lines.
+    fn render(content: &str, extension: &str) -> Vec<String> {
+        outline(content, extension)
+            .unwrap()
+            .iter()
+            .map(|line| match line.number {
+                Some(number) => format!("{} {}", number, line.text),
+                None => format!("- {}", line.text),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn outlines_rust() {
+        let content = "\
+/// Adds the numbers.
+fn add(a: i32, b: i32) -> i32 {
+    let sum = a + b;
+    sum
+}
+
+struct Point {
+    x: i32,
+}
+
+impl Point {
+    fn new() -> Self {
+        Self { x: 0 }
+    }
+    fn x(&self) -> i32 { self.x }
+}
+";
+        assert_eq!(
+            render(content, "rs"),
+            [
+                "1 /// Adds the numbers.",
+                "2 fn add(a: i32, b: i32) -> i32 {",
+                "-     ...",
+                "5 }",
+                "6 ",
+                "7 struct Point {",
+                "8     x: i32,",
+                "9 }",
+                "10 ",
+                "11 impl Point {",
+                "12     fn new() -> Self {",
+                "-         ...",
+                "14     }",
+                "15     fn x(&self) -> i32 { self.x }",
+                "16 }",
+            ]
+        );
+    }
+
+    #[test]
+    fn outlines_python() {
+        let content = "\
+class Parser:
+    \"\"\"Parses text.\"\"\"
+
+    def parse(self, text):
+        \"\"\"Splits the text.\"\"\"
+        tokens = text.split()
+        return tokens
+
+    def size(self): return 1
+
+
+def main():
+    Parser().parse(\"a b\")
+";
+        assert_eq!(
+            render(content, "py"),
+            [
+                "1 class Parser:",
+                "2     \"\"\"Parses text.\"\"\"",
+                "3 ",
+                "4     def parse(self, text):",
+                "5         \"\"\"Splits the text.\"\"\"",
+                "-         ...",
+                "8 ",
+                "9     def size(self): return 1",
+                "10 ",
+                "11 ",
+                "12 def main():",
+                "-     ...",
+            ]
+        );
+    }
+
+    #[test]
+    fn outlines_javascript() {
+        let content = "\
+function render(items) {
+  return items.map((item) => {
+    return item.name;
+  });
+}
+
+const total = (items) => items.length;
+
+class Cart {
+  add(item) {
+    this.items.push(item);
+  }
+}
+";
+        assert_eq!(
+            render(content, "js"),
+            [
+                "1 function render(items) {",
+                "-   ...",
+                "5 }",
+                "6 ",
+                "7 const total = (items) => items.length;",
+                "8 ",
+                "9 class Cart {",
+                "10   add(item) {",
+                "-     ...",
+                "12   }",
+                "13 }",
+            ]
+        );
+    }
+
+    #[test]
+    fn outlines_typescript() {
+        let content = "\
+interface Shape {
+  area(): number;
+}
+
+export function area(shape: Shape): number {
+  const value = shape.area();
+  return value;
+}
+";
+        assert_eq!(
+            render(content, "ts"),
+            [
+                "1 interface Shape {",
+                "2   area(): number;",
+                "3 }",
+                "4 ",
+                "5 export function area(shape: Shape): number {",
+                "-   ...",
+                "8 }",
+            ]
+        );
+    }
+
+    #[test]
+    fn outlines_tsx() {
+        let content = "\
+export function App() {
+  const title = \"App\";
+  return <h1>{title}</h1>;
+}
+";
+        assert_eq!(
+            render(content, "tsx"),
+            ["1 export function App() {", "-   ...", "4 }"]
+        );
+    }
+
+    #[test]
+    fn outlines_go() {
+        let content = "\
+package main
+
+type Server struct {
+\tport int
+}
+
+func (s *Server) Start() error {
+\ts.port = 80
+\treturn nil
+}
+
+func main() {
+\thandler := func() {
+\t\tprintln(\"hi\")
+\t}
+\thandler()
+}
+";
+        assert_eq!(
+            render(content, "go"),
+            [
+                "1 package main",
+                "2 ",
+                "3 type Server struct {",
+                "4 \tport int",
+                "5 }",
+                "6 ",
+                "7 func (s *Server) Start() error {",
+                "- \t...",
+                "10 }",
+                "11 ",
+                "12 func main() {",
+                "- \t...",
+                "17 }",
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_unsupported_languages() {
+        assert!(outline("fn main() {}", "c").is_none());
+    }
+}
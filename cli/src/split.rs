@@ -0,0 +1,524 @@
+//! # Split Module
+//!
+//! Handles splitting a prompt that is over a token limit into numbered chunks. The files are
+//! packed into the chunks in order and a file is only cut when it doesn't fit in a chunk on its
+//! own. The source tree, git diff, git log and issue go in the first chunk and every chunk starts
+//! with a "Part X of Y" header.
+
+use crate::template::{render_output, OutputFormat};
+use anyhow::{anyhow, Result};
+use handlebars::Handlebars;
+use serde_json::{json, Value};
+use std::path::Path;
+use tiktoken_rs::CoreBPE;
+
+/// Minimum number of tokens left for the files in each chunk.
+const MIN_PART_TOKENS: usize = 100;
+
+/// Markdown code block delimiter wrapping the file contents.
+const CODE_BLOCK_TICKS: &str = "```";
+
+/// Splits the prompt data into chunks that each fit in the token limit and renders them.
+///
+/// ### Arguments
+///
+/// - `registry`: The handlebars registry.
+/// - `template_name`: The handlebars template name.
+/// - `json_data`: The template data of the whole prompt.
+/// - `format`: The output format.
+/// - `bpe`: The tokenizer.
+/// - `max_tokens`: The token limit of each chunk.
+/// - `file_cost`: Counts the tokens a file takes up in the rendered prompt, including the
+///   template text around it.
+///
+/// ### Returns
+///
+/// - `Result<Vec<String>>`: The rendered chunks.
+///
+pub fn split_prompt(
+    registry: &Handlebars,
+    template_name: &str,
+    json_data: &Value,
+    format: OutputFormat,
+    bpe: &CoreBPE,
+    max_tokens: usize,
+    file_cost: &dyn Fn(&Value) -> Result<usize>,
+) -> Result<Vec<String>> {
+    let files = json_data["files"].as_array().cloned().unwrap_or_default();
+    let count_tokens = |text: &str| bpe.encode_with_special_tokens(text).len();
+
+    // The overhead of a chunk is the template rendered without files, with a header for the
+    // largest part numbers.
+    let header_tokens = part_header(format, files.len(), files.len())
+        .map(|header| count_tokens(&header))
+        .unwrap_or(0);
+    let first_overhead = count_tokens(&render_output(
+        registry,
+        template_name,
+        &chunk_data(json_data, Vec::new(), 0, 1),
+        format,
+    )?) + header_tokens;
+    let overhead = count_tokens(&render_output(
+        registry,
+        template_name,
+        &chunk_data(json_data, Vec::new(), 1, 2),
+        format,
+    )?) + header_tokens;
+
+    // The file parts need room for at least a few lines next to the template, and the first
+    // chunk also carries the source tree, git diff, git log and issue.
+    if overhead + MIN_PART_TOKENS > max_tokens {
+        return Err(anyhow!(
+            "--split {} is too small, each chunk needs the {} tokens of the template and at least {} tokens of files",
+            max_tokens,
+            overhead,
+            MIN_PART_TOKENS
+        ));
+    }
+    if first_overhead + MIN_PART_TOKENS > max_tokens {
+        return Err(anyhow!(
+            "--split {} is too small, the first chunk needs the {} tokens of the template, source tree, git diff, git log and issue and at least {} tokens of files",
+            max_tokens,
+            first_overhead,
+            MIN_PART_TOKENS
+        ));
+    }
+
+    let packs = pack_files(files, bpe, max_tokens, first_overhead, overhead, file_cost)?;
+    let total = packs.len();
+    packs
+        .into_iter()
+        .enumerate()
+        .map(|(idx, files)| {
+            let data = chunk_data(json_data, files, idx, total);
+            let rendered = render_output(registry, template_name, &data, format)?;
+            Ok(match part_header(format, idx + 1, total) {
+                Some(header) => header + &rendered,
+                None => rendered,
+            })
+        })
+        .collect()
+}
+
+/// Builds the template data of a chunk. Only the first chunk carries the source tree, the git
+/// diff, the git log and the issue.
+///
+/// ### Arguments
+///
+/// - `json_data`: The template data of the whole prompt.
+/// - `files`: The files of the chunk.
+/// - `idx`: The 0-based index of the chunk.
+/// - `total`: The number of chunks.
+///
+/// ### Returns
+///
+/// - `Value`: The template data of the chunk.
+///
+fn chunk_data(json_data: &Value, files: Vec<Value>, idx: usize, total: usize) -> Value {
+    let mut data = json_data.clone();
+    data["files"] = Value::Array(files);
+    data["part"] = json!({ "index": idx + 1, "total": total });
+    if idx > 0 {
+        data["source_tree"] = "".into();
+        data["git_diff"] = "".into();
+        data["git_log"] = json!([]);
+        if let Some(data) = data.as_object_mut() {
+            data.remove("github_issue");
+        }
+    }
+    data
+}
+
+/// Formats the header of a chunk, followed by the blank line separating it from the chunk.
+///
+/// ### Arguments
+///
+/// - `format`: The output format.
+/// - `index`: The 1-based index of the chunk.
+/// - `total`: The number of chunks.
+///
+/// ### Returns
+///
+/// - `Option<String>`: The header, or None for the structured formats that carry the part
+///   numbers in their data.
+///
+fn part_header(format: OutputFormat, index: usize, total: usize) -> Option<String> {
+    match format {
+        OutputFormat::Xml => Some(format!("<part>{} of {}</part>\n\n", index, total)),
+        OutputFormat::Markdown | OutputFormat::Plain => {
+            Some(format!("Part {} of {}\n\n", index, total))
+        }
+        OutputFormat::JsonStructured | OutputFormat::Yaml => None,
+    }
+}
+
+/// Packs the files into chunks in order.
+///
+/// A file that doesn't fit in the current chunk starts a new chunk, and only a file that doesn't
+/// fit in an empty chunk on its own is cut into parts that each fill a chunk.
+///
+/// ### Arguments
+///
+/// - `files`: The JSON representation of the files.
+/// - `bpe`: The tokenizer.
+/// - `max_tokens`: The token limit of each chunk.
+/// - `first_overhead`: The tokens taken up by the template in the first chunk.
+/// - `overhead`: The tokens taken up by the template in the other chunks.
+/// - `file_cost`: Counts the tokens a file takes up in the rendered prompt.
+///
+/// ### Returns
+///
+/// - `Result<Vec<Vec<Value>>>`: The files of each chunk, there is always at least one chunk, or
+///   an error if the cost of a file couldn't be counted.
+///
+fn pack_files(
+    files: Vec<Value>,
+    bpe: &CoreBPE,
+    max_tokens: usize,
+    first_overhead: usize,
+    overhead: usize,
+    file_cost: &dyn Fn(&Value) -> Result<usize>,
+) -> Result<Vec<Vec<Value>>> {
+    let mut chunks = Vec::new();
+    let mut current = Vec::new();
+    let mut used = first_overhead;
+
+    for file in files {
+        let cost = file_cost(&file)?;
+        if used + cost <= max_tokens {
+            current.push(file);
+            used += cost;
+            continue;
+        }
+
+        // The file goes in a new chunk. The first chunk is kept even without files since it
+        // carries the source tree.
+        chunks.push(std::mem::take(&mut current));
+        if overhead + cost <= max_tokens {
+            current.push(file);
+            used = overhead + cost;
+            continue;
+        }
+
+        // The file doesn't fit in an empty chunk, so each part fills a chunk.
+        let parts = split_to_fit(&file, bpe, max_tokens - overhead, file_cost)?;
+        let last = parts.len() - 1;
+        for (idx, part) in parts.into_iter().enumerate() {
+            if idx > 0 {
+                chunks.push(std::mem::take(&mut current));
+            }
+            if idx == last {
+                used = overhead + file_cost(&part)?;
+            }
+            current.push(part);
+        }
+    }
+
+    if !current.is_empty() || chunks.is_empty() {
+        chunks.push(current);
+    }
+    Ok(chunks)
+}
+
+/// Cuts a file into parts that each take up at most a number of tokens in the prompt.
+///
+/// The code of the parts is first sized from the tokens left next to the template text around
+/// the file. The code tokens are counted before the escaping of the output format, so the parts
+/// are cut smaller while one of them is still over the limit.
+///
+/// ### Arguments
+///
+/// - `file`: The JSON representation of the file.
+/// - `bpe`: The tokenizer.
+/// - `max_tokens`: The token limit of each part, including the template text around it.
+/// - `file_cost`: Counts the tokens a file takes up in the rendered prompt.
+///
+/// ### Returns
+///
+/// - `Result<Vec<Value>>`: The parts of the file, or an error if the cost of a part couldn't be
+///   counted.
+///
+fn split_to_fit(
+    file: &Value,
+    bpe: &CoreBPE,
+    max_tokens: usize,
+    file_cost: &dyn Fn(&Value) -> Result<usize>,
+) -> Result<Vec<Value>> {
+    let mut empty = file.clone();
+    empty["code"] = "".into();
+    let mut available = max_tokens.saturating_sub(file_cost(&empty)?);
+
+    loop {
+        let parts = split_file(file, bpe, available);
+        let mut largest = 0;
+        for part in &parts {
+            largest = largest.max(file_cost(part)?);
+        }
+        // A part can't be smaller than a single line.
+        if largest <= max_tokens || available == 0 {
+            return Ok(parts);
+        }
+        available = available.saturating_sub(largest - max_tokens);
+    }
+}
+
+/// Cuts a file into parts on line boundaries.
+///
+/// The parts keep the markdown code block of the file, and each part records its position in the
+/// `split` field of its JSON representation.
+///
+/// ### Arguments
+///
+/// - `file`: The JSON representation of the file.
+/// - `bpe`: The tokenizer.
+/// - `max_tokens`: The token limit of the code of each part.
+///
+/// ### Returns
+///
+/// - `Vec<Value>`: The parts of the file, each with at least one line.
+///
+fn split_file(file: &Value, bpe: &CoreBPE, max_tokens: usize) -> Vec<Value> {
+    let code = file["code"].as_str().unwrap_or("");
+    // Split the opening and closing lines of the code block off the content.
+    let (opening, content, closing) = match code
+        .strip_prefix(CODE_BLOCK_TICKS)
+        .and_then(|rest| rest.split_once('\n'))
+        .and_then(|(language, rest)| {
+            rest.strip_suffix(CODE_BLOCK_TICKS)
+                .map(|content| (format!("{}{}\n", CODE_BLOCK_TICKS, language), content))
+        }) {
+        Some((opening, content)) => (opening, content, CODE_BLOCK_TICKS),
+        None => (String::new(), code, ""),
+    };
+
+    let mut pieces = Vec::new();
+    let mut piece = String::new();
+    let mut piece_tokens = 0;
+    for line in content.split_inclusive('\n') {
+        let line_tokens = bpe.encode_with_special_tokens(line).len();
+        if piece_tokens + line_tokens > max_tokens && !piece.is_empty() {
+            pieces.push((std::mem::take(&mut piece), piece_tokens));
+            piece_tokens = 0;
+        }
+        piece.push_str(line);
+        piece_tokens += line_tokens;
+    }
+    if !piece.is_empty() || pieces.is_empty() {
+        pieces.push((piece, piece_tokens));
+    }
+
+    let total = pieces.len();
+    pieces
+        .into_iter()
+        .enumerate()
+        .map(|(idx, (piece, tokens))| {
+            let mut part = file.clone();
+            part["code"] = format!("{}{}{}", opening, piece, closing).into();
+            part["token_count"] = tokens.into();
+            part["split"] = json!({ "index": idx + 1, "total": total });
+            part
+        })
+        .collect()
+}
+
+/// Builds the path of a chunk file.
+///
+/// The chunks are named after the output file with the chunk number before the extension, such as
+/// `out-001.md` for `out.md`, or `prompt-001.<extension>` without an output file.
+///
+/// ### Arguments
+///
+/// - `output`: The output file path, if one was passed.
+/// - `format`: The output format, which gives the default extension.
+/// - `index`: The 1-based index of the chunk.
+///
+/// ### Returns
+///
+/// - `String`: The path of the chunk file.
+///
+pub fn chunk_path(output: Option<&str>, format: OutputFormat, index: usize) -> String {
+    let Some(output) = output else {
+        return format!("prompt-{:03}.{}", index, format.extension());
+    };
+    let path = Path::new(output);
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    let file_name = match path.extension() {
+        Some(extension) => format!("{}-{:03}.{}", stem, index, extension.to_string_lossy()),
+        None => format!("{}-{:03}", stem, index),
+    };
+    path.with_file_name(file_name)
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::setup_handlebars_registry;
+    use tiktoken_rs::cl100k_base;
+
+    /// Builds the JSON representation of a file with a known token count.
+    fn file(path: &str, token_count: usize) -> Value {
+        json!({ "path": path, "code": "", "token_count": token_count })
+    }
+
+    /// Counts the token count of a file and 10 tokens of template text around it.
+    fn cost(file: &Value) -> Result<usize> {
+        Ok(file["token_count"].as_u64().unwrap_or(0) as usize + 10)
+    }
+
+    fn paths(chunks: &[Vec<Value>]) -> Vec<Vec<&str>> {
+        chunks
+            .iter()
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .map(|file| file["path"].as_str().unwrap())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn packs_small_files_in_one_chunk() {
+        let bpe = cl100k_base().unwrap();
+        let files = vec![file("a.rs", 10), file("b.rs", 10), file("c.rs", 10)];
+
+        let chunks = pack_files(files, &bpe, 1000, 100, 50, &cost).unwrap();
+
+        assert_eq!(paths(&chunks), vec![vec!["a.rs", "b.rs", "c.rs"]]);
+    }
+
+    #[test]
+    fn moves_a_file_that_doesnt_fit_whole_to_the_next_chunk() {
+        let bpe = cl100k_base().unwrap();
+        let files = vec![file("a.rs", 300), file("b.rs", 300)];
+
+        let chunks = pack_files(files, &bpe, 100 + 310 + 309, 100, 100, &cost).unwrap();
+
+        assert_eq!(paths(&chunks), vec![vec!["a.rs"], vec!["b.rs"]]);
+        assert!(chunks[1][0].get("split").is_none());
+    }
+
+    #[test]
+    fn keeps_the_first_chunk_when_the_first_file_doesnt_fit_next_to_the_tree() {
+        let bpe = cl100k_base().unwrap();
+
+        let chunks = pack_files(vec![file("a.rs", 300)], &bpe, 360, 500, 50, &cost).unwrap();
+
+        assert_eq!(paths(&chunks), vec![vec![], vec!["a.rs"]]);
+    }
+
+    #[test]
+    fn splits_a_file_that_doesnt_fit_in_an_empty_chunk() {
+        let bpe = cl100k_base().unwrap();
+        let code = "let value = 1;\n".repeat(200);
+        let big = json!({ "path": "big.rs", "code": code });
+        // The code takes up twice its tokens in the prompt, as with escaping.
+        let escaped_cost = |file: &Value| {
+            Ok(10
+                + 2 * bpe
+                    .encode_with_special_tokens(file["code"].as_str().unwrap())
+                    .len())
+        };
+        let max_tokens = 50 + escaped_cost(&big).unwrap() / 3;
+
+        let chunks = pack_files(
+            vec![file("a.rs", 10), big],
+            &bpe,
+            max_tokens,
+            50,
+            50,
+            &escaped_cost,
+        )
+        .unwrap();
+
+        let parts: Vec<&Value> = chunks.iter().flatten().skip(1).collect();
+        assert!(parts.len() > 1);
+        let mut content = String::new();
+        for (idx, part) in parts.iter().enumerate() {
+            assert_eq!(part["split"]["index"], idx + 1);
+            assert_eq!(part["split"]["total"], parts.len());
+            assert!(50 + escaped_cost(part).unwrap() <= max_tokens);
+            content.push_str(part["code"].as_str().unwrap());
+        }
+        assert_eq!(content, code);
+        // The file starts a new chunk and each part has a chunk of its own.
+        assert_eq!(chunks[0].len(), 1);
+        assert!(chunks[1..].iter().all(|chunk| chunk.len() == 1));
+    }
+
+    #[test]
+    fn reports_the_errors_of_the_cost_function() {
+        let bpe = cl100k_base().unwrap();
+        let failing = |_: &Value| Err(anyhow!("render failed"));
+
+        assert!(pack_files(vec![file("a.rs", 10)], &bpe, 1000, 100, 50, &failing).is_err());
+    }
+
+    #[test]
+    fn keeps_the_code_block_around_each_part() {
+        let bpe = cl100k_base().unwrap();
+        let code = format!("```rs\n{}```", "let value = 1;\n".repeat(50));
+        let big = json!({ "path": "big.rs", "code": code });
+
+        let parts = split_file(&big, &bpe, 100);
+
+        assert!(parts.len() > 1);
+        for part in parts {
+            let code = part["code"].as_str().unwrap();
+            assert!(code.starts_with("```rs\nlet value"));
+            assert!(code.ends_with(";\n```"));
+        }
+    }
+
+    #[test]
+    fn returns_one_empty_chunk_without_files() {
+        let bpe = cl100k_base().unwrap();
+
+        let chunks = pack_files(Vec::new(), &bpe, 1000, 100, 50, &cost).unwrap();
+
+        assert_eq!(chunks.len(), 1);
+        assert!(chunks[0].is_empty());
+    }
+
+    #[test]
+    fn rejects_a_limit_below_the_first_chunk_overhead() {
+        let bpe = cl100k_base().unwrap();
+        let template = "{{source_tree}}{{#each files}}{{path}}{{/each}}";
+        let registry = setup_handlebars_registry(template, "test", OutputFormat::Plain).unwrap();
+        let json_data = json!({
+            "source_tree": "src/main.rs ".repeat(100),
+            "files": [{ "path": "a.rs", "code": "", "token_count": 10 }],
+        });
+
+        let error = split_prompt(
+            &registry,
+            "test",
+            &json_data,
+            OutputFormat::Plain,
+            &bpe,
+            300,
+            &cost,
+        )
+        .unwrap_err();
+
+        assert!(error.to_string().contains("the first chunk needs"));
+    }
+
+    #[test]
+    fn names_the_chunks_after_the_output_file() {
+        assert_eq!(
+            chunk_path(Some("out/prompt.md"), OutputFormat::Markdown, 2),
+            "out/prompt-002.md"
+        );
+        assert_eq!(
+            chunk_path(Some("prompt"), OutputFormat::Xml, 1),
+            "prompt-001"
+        );
+        assert_eq!(chunk_path(None, OutputFormat::Xml, 12), "prompt-012.xml");
+    }
+}
@@ -0,0 +1,680 @@
+//! # Symbols Module
+//!
+//! Handles the symbol level extraction of source files. The file is parsed with tree-sitter and
+//! only the requested functions, types and impl blocks are kept, with their doc comments and a
+//! few lines of surrounding context. The kept lines keep their original line numbers.
+
+use crate::files::SourceLine;
+use crate::outline::OutlineLanguage;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::path::Path;
+use tree_sitter::{Node, Parser};
+
+/// Number of context lines kept before and after each symbol.
+const CONTEXT_LINES: usize = 3;
+
+/// A symbol requested with `--symbol`.
+#[derive(Debug, Clone)]
+pub struct SymbolQuery {
+    /// The symbol as passed on the command line.
+    pub spec: String,
+    /// The glob pattern of the files to search, or None to search all files.
+    pub path: Option<String>,
+    /// The name segments, the last one is the symbol name and the others its containers.
+    pub name: Vec<String>,
+}
+
+impl SymbolQuery {
+    /// Parses a symbol query.
+    ///
+    /// The query is either a symbol name, such as `MyStruct` or `MyStruct::new`, or a file path
+    /// followed by `::` and the symbol name, such as `src/files.rs::traverse_directory`. The part
+    /// before the first `::` is only read as a path when it contains a `/` or a `*`, or ends with
+    /// the extension of a supported language, so `Outer.inner::method` is a symbol name. The name
+    /// segments can be separated with `::` or `.`.
+    ///
+    /// ### Arguments
+    ///
+    /// - `spec`: The symbol query.
+    ///
+    /// ### Returns
+    ///
+    /// - `Result<SymbolQuery>`: The parsed query, or an error if the symbol name is empty.
+    ///
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (path, name) = match spec.split_once("::") {
+            Some((path, name)) if is_path(path) => (Some(path.to_owned()), name),
+            _ => (None, spec),
+        };
+        let name: Vec<String> = name
+            .split("::")
+            .flat_map(|segment| segment.split('.'))
+            .map(str::to_owned)
+            .collect();
+        if name.iter().any(String::is_empty) {
+            return Err(anyhow!("Invalid symbol {}: empty symbol name", spec));
+        }
+        Ok(Self {
+            spec: spec.to_owned(),
+            path,
+            name,
+        })
+    }
+
+    /// Whether the query matches a symbol path.
+    fn matches(&self, qualified: &[String]) -> bool {
+        qualified.ends_with(&self.name)
+    }
+}
+
+/// Whether the prefix of a symbol query is a file path or glob pattern rather than a container
+/// name.
+fn is_path(prefix: &str) -> bool {
+    prefix.contains(['/', '*'])
+        || Path::new(prefix)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| OutlineLanguage::from_extension(extension).is_some())
+}
+
+/// A symbol extracted from a file.
+#[derive(Debug, Clone, Serialize)]
+pub struct Symbol {
+    /// The qualified name of the symbol, such as `Tree::insert`.
+    pub name: String,
+    /// The kind of the symbol, such as `function` or `impl`.
+    pub kind: &'static str,
+    /// The 1-based first line of the symbol, including its doc comments.
+    pub start_line: usize,
+    /// The 1-based last line of the symbol.
+    pub end_line: usize,
+}
+
+/// The symbols extracted from a file.
+#[derive(Debug)]
+pub struct Extraction<'a> {
+    /// The lines of the symbols and their context, numbered with their original line number.
+    pub lines: Vec<SourceLine<'a>>,
+    /// The symbols found.
+    pub symbols: Vec<Symbol>,
+    /// The indices of the queries that matched a symbol.
+    pub matched: Vec<usize>,
+}
+
+/// A symbol definition found in the syntax tree.
+#[derive(Debug)]
+struct Definition {
+    /// The name segments of the symbol, including its containers.
+    qualified: Vec<String>,
+    /// The kind of the symbol.
+    kind: &'static str,
+    /// The 0-based, inclusive row range of the symbol.
+    rows: (usize, usize),
+}
+
+/// Extracts the requested symbols from a source file.
+///
+/// ### Arguments
+///
+/// - `content`: The file content.
+/// - `extension`: The file extension, used to pick the language.
+/// - `queries`: The queries that apply to the file, with their index.
+///
+/// ### Returns
+///
+/// - `Option<Extraction>`: The extracted lines and symbols, or None if the language isn't
+///   supported, the file can't be parsed or no symbol matched.
+///
+pub fn extract_symbols<'a>(
+    content: &'a str,
+    extension: &str,
+    queries: &[(usize, &SymbolQuery)],
+) -> Option<Extraction<'a>> {
+    let language = OutlineLanguage::from_extension(extension)?;
+    let mut parser = Parser::new();
+    parser.set_language(&language.grammar()).ok()?;
+    let tree = parser.parse(content, None)?;
+
+    let mut definitions = Vec::new();
+    collect_definitions(
+        tree.root_node(),
+        content,
+        language,
+        &mut Vec::new(),
+        &mut definitions,
+    );
+
+    let mut symbols = Vec::new();
+    let mut matched = Vec::new();
+    let mut ranges = Vec::new();
+    for definition in definitions {
+        let hits: Vec<usize> = queries
+            .iter()
+            .filter(|(_, query)| query.matches(&definition.qualified))
+            .map(|(idx, _)| *idx)
+            .collect();
+        if hits.is_empty() {
+            continue;
+        }
+        matched.extend(hits);
+        ranges.push(definition.rows);
+        symbols.push(Symbol {
+            name: definition.qualified.join("::"),
+            kind: definition.kind,
+            start_line: definition.rows.0 + 1,
+            end_line: definition.rows.1 + 1,
+        });
+    }
+    if symbols.is_empty() {
+        return None;
+    }
+    matched.sort_unstable();
+    matched.dedup();
+
+    let ranges = merge_ranges(ranges, content.lines().count());
+    Some(Extraction {
+        lines: SourceLine::select(content, &ranges),
+        symbols,
+        matched,
+    })
+}
+
+/// Adds the context lines to the symbol ranges and merges the ranges that overlap or touch.
+///
+/// ### Arguments
+///
+/// - `ranges`: The 0-based, inclusive row ranges of the symbols.
+/// - `line_count`: The number of lines in the file.
+///
+/// ### Returns
+///
+/// - `Vec<(usize, usize)>`: The sorted and merged row ranges.
+///
+fn merge_ranges(mut ranges: Vec<(usize, usize)>, line_count: usize) -> Vec<(usize, usize)> {
+    let last_row = line_count.saturating_sub(1);
+    ranges.sort_unstable();
+    let mut merged: Vec<(usize, usize)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        let start = start.saturating_sub(CONTEXT_LINES);
+        let end = (end + CONTEXT_LINES).min(last_row);
+        match merged.last_mut() {
+            Some((_, last_end)) if start <= *last_end + 1 => *last_end = (*last_end).max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// Collects the symbol definitions of a syntax tree.
+///
+/// ### Arguments
+///
+/// - `node`: The node to search.
+/// - `source`: The file content.
+/// - `language`: The language of the file.
+/// - `containers`: The names of the enclosing impl blocks, traits, modules and classes.
+/// - `definitions`: The definitions found.
+///
+fn collect_definitions(
+    node: Node,
+    source: &str,
+    language: OutlineLanguage,
+    containers: &mut Vec<String>,
+    definitions: &mut Vec<Definition>,
+) {
+    let definition = symbol_kind(node, language).and_then(|kind| {
+        let name = symbol_name(node, source, language)?;
+        Some((kind, name))
+    });
+
+    let mut container = None;
+    if let Some((kind, name)) = definition {
+        let mut qualified = containers.clone();
+        // Go methods are qualified with their receiver type.
+        if let Some(receiver) = go_receiver(node, source) {
+            qualified.push(receiver);
+        }
+        qualified.push(name.clone());
+        definitions.push(Definition {
+            qualified,
+            kind,
+            rows: symbol_rows(node),
+        });
+        if is_container(kind) {
+            container = Some(name);
+        }
+    }
+
+    let pushed = container.is_some();
+    if let Some(name) = container {
+        containers.push(name);
+    }
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        collect_definitions(child, source, language, containers, definitions);
+    }
+    if pushed {
+        containers.pop();
+    }
+}
+
+/// Looks up the kind of symbol a node defines.
+///
+/// ### Arguments
+///
+/// - `node`: The node.
+/// - `language`: The language of the file.
+///
+/// ### Returns
+///
+/// - `Option<&str>`: The symbol kind, or None if the node isn't a symbol definition.
+///
+fn symbol_kind(node: Node, language: OutlineLanguage) -> Option<&'static str> {
+    let kind = match language {
+        OutlineLanguage::Rust => match node.kind() {
+            "function_item" | "function_signature_item" => "function",
+            "struct_item" => "struct",
+            "enum_item" => "enum",
+            "union_item" => "union",
+            "trait_item" => "trait",
+            "impl_item" => "impl",
+            "type_item" => "type",
+            "const_item" => "const",
+            "static_item" => "static",
+            "mod_item" => "module",
+            "macro_definition" => "macro",
+            _ => return None,
+        },
+        OutlineLanguage::Python => match node.kind() {
+            "function_definition" => "function",
+            "class_definition" => "class",
+            _ => return None,
+        },
+        OutlineLanguage::JavaScript | OutlineLanguage::TypeScript | OutlineLanguage::Tsx => {
+            match node.kind() {
+                "function_declaration" | "generator_function_declaration" => "function",
+                "class_declaration" | "abstract_class_declaration" => "class",
+                "method_definition" => "method",
+                "interface_declaration" => "interface",
+                "type_alias_declaration" => "type",
+                "enum_declaration" => "enum",
+                // Functions assigned to a variable, such as `const f = () => {}`.
+                "variable_declarator"
+                    if node.child_by_field_name("value").is_some_and(|value| {
+                        matches!(
+                            value.kind(),
+                            "arrow_function" | "function_expression" | "function"
+                        )
+                    }) =>
+                {
+                    "function"
+                }
+                _ => return None,
+            }
+        }
+        OutlineLanguage::Go => match node.kind() {
+            "function_declaration" => "function",
+            "method_declaration" => "method",
+            "type_spec" => "type",
+            _ => return None,
+        },
+    };
+    Some(kind)
+}
+
+/// Whether a symbol kind contains other symbols that are qualified with its name.
+fn is_container(kind: &str) -> bool {
+    matches!(kind, "impl" | "trait" | "module" | "class")
+}
+
+/// Looks up the name of a symbol definition.
+///
+/// ### Arguments
+///
+/// - `node`: The definition node.
+/// - `source`: The file content.
+/// - `language`: The language of the file.
+///
+/// ### Returns
+///
+/// - `Option<String>`: The symbol name, without its generic parameters.
+///
+fn symbol_name(node: Node, source: &str, language: OutlineLanguage) -> Option<String> {
+    // Impl blocks are named after the type they implement, such as `Tree` in
+    // `impl<T> Display for Tree<T>`.
+    let field = if language == OutlineLanguage::Rust && node.kind() == "impl_item" {
+        "type"
+    } else {
+        "name"
+    };
+    let text = node
+        .child_by_field_name(field)?
+        .utf8_text(source.as_bytes())
+        .ok()?;
+    Some(type_name(text))
+}
+
+/// Strips the generic parameters, pointer marker and path of a type.
+fn type_name(text: &str) -> String {
+    let text = text.trim_start_matches(['*', '&']);
+    let text = text.split(['<', '[']).next().unwrap_or(text);
+    text.rsplit("::").next().unwrap_or(text).trim().to_owned()
+}
+
+/// Looks up the receiver type of a Go method.
+fn go_receiver(node: Node, source: &str) -> Option<String> {
+    if node.kind() != "method_declaration" {
+        return None;
+    }
+    let receiver = node.child_by_field_name("receiver")?.named_child(0)?;
+    let text = receiver
+        .child_by_field_name("type")?
+        .utf8_text(source.as_bytes())
+        .ok()?;
+    Some(type_name(text))
+}
+
+/// Computes the rows of a symbol definition.
+///
+/// The range is widened to the declaration or export statement wrapping the definition, the
+/// decorators, and the doc comments and attributes directly above it.
+///
+/// ### Arguments
+///
+/// - `node`: The definition node.
+///
+/// ### Returns
+///
+/// - `(usize, usize)`: The 0-based, inclusive row range.
+///
+fn symbol_rows(node: Node) -> (usize, usize) {
+    let mut node = node;
+    while let Some(parent) = node.parent() {
+        let wraps = match parent.kind() {
+            "decorated_definition" | "export_statement" => true,
+            // Only wrap declarations of a single variable or type.
+            "lexical_declaration" | "variable_declaration" | "type_declaration" => {
+                parent.named_child_count() == 1
+            }
+            _ => false,
+        };
+        if !wraps {
+            break;
+        }
+        node = parent;
+    }
+
+    let mut start = node.start_position().row;
+    let mut sibling = node.prev_sibling();
+    while let Some(previous) = sibling {
+        let is_leading = matches!(
+            previous.kind(),
+            "line_comment" | "block_comment" | "comment" | "attribute_item" | "decorator"
+        );
+        if !is_leading || previous.end_position().row + 1 < start {
+            break;
+        }
+        start = previous.start_position().row;
+        sibling = previous.prev_sibling();
+    }
+
+    (start, node.end_position().row)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(spec: &str) -> (Option<String>, Vec<String>) {
+        let query = SymbolQuery::parse(spec).unwrap();
+        (query.path, query.name)
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    /// Extracts a symbol and returns the qualified name, kind and line range of each match.
+    fn extract(
+        content: &str,
+        extension: &str,
+        spec: &str,
+    ) -> Vec<(String, &'static str, usize, usize)> {
+        let query = SymbolQuery::parse(spec).unwrap();
+        extract_symbols(content, extension, &[(0, &query)])
+            .map(|extraction| {
+                extraction
+                    .symbols
+                    .into_iter()
+                    .map(|symbol| (symbol.name, symbol.kind, symbol.start_line, symbol.end_line))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn parses_symbol_names() {
+        assert_eq!(query("MyStruct"), (None, names(&["MyStruct"])));
+        assert_eq!(query("MyStruct::new"), (None, names(&["MyStruct", "new"])));
+        assert_eq!(query("Outer.inner"), (None, names(&["Outer", "inner"])));
+        assert_eq!(
+            query("Outer.inner::method"),
+            (None, names(&["Outer", "inner", "method"]))
+        );
+        assert_eq!(
+            query("mod.Type::new"),
+            (None, names(&["mod", "Type", "new"]))
+        );
+    }
+
+    #[test]
+    fn parses_symbol_paths() {
+        assert_eq!(
+            query("src/files.rs::traverse_directory"),
+            (
+                Some("src/files.rs".to_owned()),
+                names(&["traverse_directory"])
+            )
+        );
+        assert_eq!(
+            query("files.rs::Tree::insert"),
+            (Some("files.rs".to_owned()), names(&["Tree", "insert"]))
+        );
+        assert_eq!(
+            query("*.py::Parser.parse"),
+            (Some("*.py".to_owned()), names(&["Parser", "parse"]))
+        );
+        assert_eq!(
+            query("main.go::Server.Start"),
+            (Some("main.go".to_owned()), names(&["Server", "Start"]))
+        );
+        assert_eq!(
+            query("src/lib::run"),
+            (Some("src/lib".to_owned()), names(&["run"]))
+        );
+    }
+
+    #[test]
+    fn rejects_empty_symbol_names() {
+        for spec in ["", "src/main.rs::", "Tree::", "Tree..insert"] {
+            assert!(SymbolQuery::parse(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn extracts_rust_symbols() {
+        let content = "\
+use std::fmt;
+
+/// A tree.
+#[derive(Debug)]
+pub struct Tree {
+    size: usize,
+}
+
+impl Tree {
+    /// Inserts a node.
+    pub fn insert(&mut self) {
+        self.size += 1;
+    }
+}
+
+mod nested {
+    pub fn insert() {}
+}
+";
+        assert_eq!(
+            extract(content, "rs", "Tree"),
+            [
+                ("Tree".to_owned(), "struct", 3, 7),
+                ("Tree".to_owned(), "impl", 9, 14),
+            ]
+        );
+        assert_eq!(
+            extract(content, "rs", "Tree::insert"),
+            [("Tree::insert".to_owned(), "function", 10, 13),]
+        );
+        assert_eq!(extract(content, "rs", "insert").len(), 2);
+        assert!(extract(content, "rs", "Missing").is_empty());
+    }
+
+    #[test]
+    fn keeps_the_context_lines_of_the_symbols() {
+        let content = (1..=20)
+            .map(|n| format!("fn f{}() {{}}", n))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let query = SymbolQuery::parse("f10").unwrap();
+        let extraction = extract_symbols(&content, "rs", &[(0, &query)]).unwrap();
+
+        let numbers: Vec<usize> = extraction
+            .lines
+            .iter()
+            .filter_map(|line| line.number)
+            .collect();
+        assert_eq!(numbers, [7, 8, 9, 10, 11, 12, 13]);
+        assert_eq!(extraction.matched, [0]);
+    }
+
+    #[test]
+    fn extracts_python_symbols() {
+        let content = "\
+class Parser:
+    @staticmethod
+    def parse(text):
+        return text
+
+
+def parse(text):
+    return Parser.parse(text)
+";
+        assert_eq!(
+            extract(content, "py", "Parser.parse"),
+            [("Parser::parse".to_owned(), "function", 2, 4),]
+        );
+        assert_eq!(
+            extract(content, "py", "Parser"),
+            [("Parser".to_owned(), "class", 1, 4),]
+        );
+        assert_eq!(extract(content, "py", "parse").len(), 2);
+    }
+
+    #[test]
+    fn extracts_javascript_symbols() {
+        let content = "\
+// Renders the page.
+export function render() {
+  return view();
+}
+
+const view = () => {
+  return 'page';
+};
+
+class Page {
+  show() {}
+}
+";
+        assert_eq!(
+            extract(content, "js", "render"),
+            [("render".to_owned(), "function", 1, 4),]
+        );
+        assert_eq!(
+            extract(content, "js", "view"),
+            [("view".to_owned(), "function", 6, 8),]
+        );
+        assert_eq!(
+            extract(content, "js", "Page.show"),
+            [("Page::show".to_owned(), "method", 11, 11),]
+        );
+    }
+
+    #[test]
+    fn extracts_typescript_symbols() {
+        let content = "\
+interface Shape {
+  area(): number;
+}
+
+type Point = { x: number };
+
+enum Color {
+  Red,
+}
+";
+        assert_eq!(
+            extract(content, "ts", "Shape"),
+            [("Shape".to_owned(), "interface", 1, 3),]
+        );
+        assert_eq!(
+            extract(content, "ts", "Point"),
+            [("Point".to_owned(), "type", 5, 5),]
+        );
+        assert_eq!(
+            extract(content, "ts", "Color"),
+            [("Color".to_owned(), "enum", 7, 9),]
+        );
+    }
+
+    #[test]
+    fn extracts_tsx_symbols() {
+        let content = "\
+export const App = () => {
+  return <div />;
+};
+";
+        assert_eq!(
+            extract(content, "tsx", "App"),
+            [("App".to_owned(), "function", 1, 3),]
+        );
+    }
+
+    #[test]
+    fn extracts_go_symbols() {
+        let content = "\
+package main
+
+// Server serves requests.
+type Server struct{}
+
+func (s *Server) Start() error {
+\treturn nil
+}
+
+func main() {}
+";
+        assert_eq!(
+            extract(content, "go", "Server"),
+            [("Server".to_owned(), "type", 3, 4),]
+        );
+        assert_eq!(
+            extract(content, "go", "Server.Start"),
+            [("Server::Start".to_owned(), "method", 6, 8),]
+        );
+        assert_eq!(
+            extract(content, "go", "main"),
+            [("main".to_owned(), "function", 10, 10),]
+        );
+    }
+}
@@ -4,12 +4,169 @@
 //! engine, rendering the template with the data, copying the output to the clipboard, and
 //! writing the output to a file.
 //!
-//! Right now does not support user-defined variables but this is planned in the future.
+//! User-defined variables can be passed on the command line or loaded from a TOML or JSON file
+//! and are merged into the template data.
 
-use super::constants::{CUSTOM_TEMPLATE_NAME, DEFAULT_TEMPLATE_NAME};
+use super::constants::{
+    CUSTOM_TEMPLATE_NAME, DEFAULT_TEMPLATE_NAME, PLAIN_TEMPLATE_NAME, XML_TEMPLATE_NAME,
+};
 use anyhow::{anyhow, Context, Result};
-use handlebars::{no_escape, Handlebars};
-use std::path::PathBuf;
+use clap::ValueEnum;
+use handlebars::template::{HelperTemplate, Parameter, TemplateElement};
+use handlebars::{handlebars_helper, no_escape, Handlebars, Path as TemplatePath, Template};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// A template bundled into the binary.
+#[derive(Debug)]
+pub struct BuiltinTemplate {
+    /// The name used to select the template with the `--template` option.
+    pub name: &'static str,
+    /// One line description of the template.
+    pub description: &'static str,
+    /// The Handlebars template content.
+    pub content: &'static str,
+}
+
+/// Includes a bundled template file from the templates directory at compile time.
+macro_rules! bundled_template {
+    ($file:literal) => {
+        include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/src/templates/",
+            $file
+        ))
+    };
+}
+
+/// The templates bundled into the binary.
+pub const BUILTIN_TEMPLATES: &[BuiltinTemplate] = &[
+    BuiltinTemplate {
+        name: DEFAULT_TEMPLATE_NAME,
+        description: "Simple template with the project path, source tree, and code blocks.",
+        content: bundled_template!("default_template.hbs"),
+    },
+    BuiltinTemplate {
+        name: "documentation",
+        description: "Prompt for documenting code following the HIVE lab documentation guidelines.",
+        content: bundled_template!("documentation_template.hbs"),
+    },
+    BuiltinTemplate {
+        name: "git_commit",
+        description: "Concise and accurate git commit message for the staged or unstaged diff.",
+        content: bundled_template!("git_commit.hbs"),
+    },
+    BuiltinTemplate {
+        name: "git_issue",
+        description: "Implement changes based on a Github issue.",
+        content: bundled_template!("git_issue.hbs"),
+    },
+    BuiltinTemplate {
+        name: "code_optimization",
+        description: "Optimize code in time and space complexity.",
+        content: bundled_template!("code_optimization.hbs"),
+    },
+];
+
+/// The template used by the XML output format when no template is passed.
+const XML_TEMPLATE: &str = bundled_template!("xml_template.hbs");
+
+/// The template used by the plain output format when no template is passed.
+const PLAIN_TEMPLATE: &str = bundled_template!("plain_template.hbs");
+
+/// The format of the generated prompt.
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// Markdown with the code in fenced code blocks.
+    #[default]
+    Markdown,
+    /// XML documents, with each file in a `<document>` tag and the values escaped.
+    Xml,
+    /// The template data as JSON, without rendering a template.
+    JsonStructured,
+    /// The template data as YAML, without rendering a template.
+    Yaml,
+    /// Plain text without markdown code blocks.
+    Plain,
+}
+
+impl OutputFormat {
+    /// Whether the format serializes the template data instead of rendering a template.
+    pub fn is_structured(self) -> bool {
+        matches!(self, Self::JsonStructured | Self::Yaml)
+    }
+
+    /// The file extension of the prompts written in the format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Markdown => "md",
+            Self::Xml => "xml",
+            Self::JsonStructured => "json",
+            Self::Yaml => "yaml",
+            Self::Plain => "txt",
+        }
+    }
+
+    /// Whether the file contents are wrapped in markdown code blocks.
+    pub fn uses_codeblocks(self) -> bool {
+        self == Self::Markdown
+    }
+
+    /// Whether the file lines are prefixed with their line number. The XML documents hold the
+    /// raw file contents.
+    pub fn uses_line_numbers(self) -> bool {
+        self != Self::Xml
+    }
+}
+
+handlebars_helper!(inc: |value: u64| value + 1);
+handlebars_helper!(with_code: |files: array| files
+    .iter()
+    .filter(|file| file["code"].as_str().is_some_and(|code| !code.is_empty()))
+    .cloned()
+    .collect::<Vec<_>>());
+
+/// Escapes the XML special characters of a template value.
+///
+/// ### Arguments
+///
+/// - `value`: The template value.
+///
+/// ### Returns
+///
+/// - `String`: The escaped value.
+///
+pub fn xml_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Looks up a bundled template by name.
+///
+/// ### Arguments
+///
+/// - `name`: The name of the bundled template.
+///
+/// ### Returns
+///
+/// - `Option<&'static BuiltinTemplate>`: The bundled template, if one exists with that name.
+///
+pub fn find_builtin_template(name: &str) -> Option<&'static BuiltinTemplate> {
+    BUILTIN_TEMPLATES
+        .iter()
+        .find(|template| template.name == name)
+}
 
 /// Sets up the Handlebars template engine.
 ///
@@ -17,6 +174,7 @@ use std::path::PathBuf;
 ///
 /// - `template_content`: The Handlebars template content string.
 /// - `template_name`: The name of the template.
+/// - `format`: The output format, which decides how the variable values are escaped.
 ///
 /// ### Returns
 ///
@@ -25,13 +183,24 @@ use std::path::PathBuf;
 pub fn setup_handlebars_registry(
     template_content: &str,
     template_name: &str,
+    format: OutputFormat,
 ) -> Result<Handlebars<'static>> {
     // Create the handlebars registry.
     let mut handlebars = Handlebars::new();
     // By default, Handlebars automatically escapes HTML special characters in variable values to
     // prevent potential cross-site scripting attacks. Since we are just creating formatted
     // prompts, if the variable contents are HTML we want the actual HTML, not the escaped values.
-    handlebars.register_escape_fn(no_escape);
+    // The XML output format escapes the values so the file contents can't break the document
+    // structure.
+    match format {
+        OutputFormat::Xml => handlebars.register_escape_fn(xml_escape),
+        _ => handlebars.register_escape_fn(no_escape),
+    }
+    // `{{inc @index}}` gives 1-based indices.
+    handlebars.register_helper("inc", Box::new(inc));
+    // `{{#each (with_code files)}}` skips the files without code, so `@index` only counts the
+    // rendered files.
+    handlebars.register_helper("with_code", Box::new(with_code));
     // Add a template to the Handlebars registry. Allows for pre-compiling and storing the template
     // in memory.
     handlebars
@@ -41,35 +210,52 @@ pub fn setup_handlebars_registry(
     Ok(handlebars)
 }
 
-/// Retrieve the template content and name based on the user passed arguments. If no template
-/// argument is passed by the user defaults to the default template.
+/// Retrieve the template content and name based on the user passed arguments. The argument can
+/// either be a path to a template file or the name of a bundled template. If no template argument
+/// is passed by the user defaults to the default template of the output format.
 ///
 /// ### Arguments
 ///
 /// - `path`: The Option containing the PathBuf from the user passed arguments.
+/// - `format`: The output format.
 ///
 /// ### Returns
 ///
 /// - `Result<(String, &str)>`: A tuple containing the template content and name.
 ///
-pub fn get_template<'a>(path: &'a Option<PathBuf>) -> Result<(String, &'a str)> {
-    // Grab the custom template content if provided.
-    if let Some(template_path) = path {
-        let content = std::fs::read_to_string(template_path)
-            .context("Failed to read custom template path.")?;
-        Ok((content, CUSTOM_TEMPLATE_NAME))
-    // Fallback to default template.
-    } else {
-        // Use the include_str! macro to include the contents of the file as a string literal with a
-        // `static lifetime in the code at compile time.
-        Ok((
-            include_str!(concat!(
-                env!("CARGO_MANIFEST_DIR"),
-                "/src/templates/default_template.hbs"
-            ))
-            .to_owned(),
-            DEFAULT_TEMPLATE_NAME,
-        ))
+pub fn get_template(path: &Option<PathBuf>, format: OutputFormat) -> Result<(String, &str)> {
+    match path {
+        // Grab the custom template content if the path exists.
+        Some(template_path) if template_path.is_file() => {
+            let content = std::fs::read_to_string(template_path)
+                .context("Failed to read custom template path.")?;
+            Ok((content, CUSTOM_TEMPLATE_NAME))
+        }
+        // Otherwise the argument has to name a bundled template.
+        Some(template_path) => {
+            let name = template_path.to_string_lossy();
+            let template = find_builtin_template(&name).ok_or_else(|| {
+                anyhow!(
+                    "Template '{}' is neither a template file nor a built-in template. Available templates: {}",
+                    name,
+                    BUILTIN_TEMPLATES
+                        .iter()
+                        .map(|template| template.name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })?;
+            Ok((template.content.to_owned(), template.name))
+        }
+        // Fallback to the default template of the output format.
+        None => match format {
+            OutputFormat::Xml => Ok((XML_TEMPLATE.to_owned(), XML_TEMPLATE_NAME)),
+            OutputFormat::Plain => Ok((PLAIN_TEMPLATE.to_owned(), PLAIN_TEMPLATE_NAME)),
+            _ => Ok((
+                BUILTIN_TEMPLATES[0].content.to_owned(),
+                DEFAULT_TEMPLATE_NAME,
+            )),
+        },
     }
 }
 
@@ -95,3 +281,397 @@ pub fn render_template(
         .map_err(|e| anyhow!("Failed to render template: {}", e))?;
     Ok(rendered_output.trim().to_owned())
 }
+
+/// Generates the prompt in the output format. The structured formats serialize the template data
+/// and the other formats render the template.
+///
+/// ### Arguments
+///
+/// - `registry`: The handlebars registry.
+/// - `template_name`: The handlebars template name.
+/// - `json_data`: The formatted JSON data.
+/// - `format`: The output format.
+///
+/// ### Returns
+///
+/// - `Result<String>`: The generated prompt.
+///
+pub fn render_output(
+    registry: &Handlebars,
+    template_name: &str,
+    json_data: &serde_json::Value,
+    format: OutputFormat,
+) -> Result<String> {
+    match format {
+        OutputFormat::JsonStructured => serde_json::to_string_pretty(json_data)
+            .map_err(|e| anyhow!("Failed to serialize the template data to JSON: {}", e)),
+        OutputFormat::Yaml => serde_yaml_ng::to_string(json_data)
+            .map_err(|e| anyhow!("Failed to serialize the template data to YAML: {}", e)),
+        _ => render_template(registry, template_name, json_data),
+    }
+}
+
+/// Parses a user-defined template variable passed as `key=value`.
+///
+/// ### Arguments
+///
+/// - `var`: The raw variable argument.
+///
+/// ### Returns
+///
+/// - `Result<(String, String)>`: The variable name and value.
+///
+pub fn parse_template_var(var: &str) -> Result<(String, String)> {
+    let (key, value) = var
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Invalid variable '{}', expected KEY=VALUE.", var))?;
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(anyhow!("Invalid variable '{}', the key is empty.", var));
+    }
+    Ok((key.to_owned(), value.to_owned()))
+}
+
+/// Loads user-defined template variables from a TOML or JSON file. The format is picked based on
+/// the file extension.
+///
+/// ### Arguments
+///
+/// - `path`: The path to the variables file.
+///
+/// ### Returns
+///
+/// - `Result<Map<String, Value>>`: The top level variables in the file.
+///
+pub fn load_vars_file(path: &Path) -> Result<Map<String, Value>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read variables file: {}", path.display()))?;
+    let vars: Value = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => toml::from_str(&content)
+            .with_context(|| format!("Failed to parse TOML variables file: {}", path.display()))?,
+        Some("json") => serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse JSON variables file: {}", path.display()))?,
+        _ => {
+            return Err(anyhow!(
+                "Unsupported variables file '{}', expected a .toml or .json file.",
+                path.display()
+            ))
+        }
+    };
+
+    match vars {
+        Value::Object(map) => Ok(map),
+        _ => Err(anyhow!(
+            "Variables file '{}' must contain a table of variables.",
+            path.display()
+        )),
+    }
+}
+
+/// Merges the user-defined template variables. The command line variables take precedence over
+/// the variables file, which takes precedence over the config file variables.
+///
+/// ### Arguments
+///
+/// - `config_vars`: The variables of the config file.
+/// - `vars_file`: The path to the variables file, if any.
+/// - `vars`: The variables passed on the command line.
+///
+/// ### Returns
+///
+/// - `Result<Map<String, Value>>`: The merged variables, or an error if the variables file can't
+///   be loaded.
+///
+pub fn merge_template_vars(
+    config_vars: &Map<String, Value>,
+    vars_file: Option<&Path>,
+    vars: &[(String, String)],
+) -> Result<Map<String, Value>> {
+    let mut merged = config_vars.clone();
+    if let Some(path) = vars_file {
+        merged.extend(load_vars_file(path)?);
+    }
+    for (key, value) in vars {
+        merged.insert(key.to_owned(), value.to_owned().into());
+    }
+    Ok(merged)
+}
+
+/// Finds the variables referenced by the template that are missing from the template data.
+///
+/// Only variables resolved against the top level data are checked. Variables inside `each` and
+/// `with` blocks are resolved against a nested context and are skipped, and so are the conditions
+/// of `if` and `unless` blocks since those already handle missing values.
+///
+/// ### Arguments
+///
+/// - `template_content`: The Handlebars template content string.
+/// - `json_data`: The data the template will be rendered with.
+///
+/// ### Returns
+///
+/// - `Result<Vec<String>>`: The sorted names of the missing variables.
+///
+pub fn find_missing_variables(template_content: &str, json_data: &Value) -> Result<Vec<String>> {
+    let template = Template::compile(template_content)
+        .map_err(|e| anyhow!("Failed to parse the Handlebars template: {}", e))?;
+
+    let mut referenced = BTreeSet::new();
+    collect_variables(&template, false, &mut referenced);
+
+    Ok(referenced
+        .into_iter()
+        .filter(|name| json_data.get(name).is_none_or(Value::is_null))
+        .collect())
+}
+
+/// Checks whether a template references a top level variable, either to render it or as the
+/// parameter of a helper such as `if` or `each`.
+///
+/// ### Arguments
+///
+/// - `template_content`: The Handlebars template content.
+/// - `name`: The variable name, such as `git_diff`.
+///
+/// ### Returns
+///
+/// - `Result<bool>`: Whether the variable is referenced, or an error if the template can't be
+///   parsed.
+///
+pub fn template_references(template_content: &str, name: &str) -> Result<bool> {
+    let template = Template::compile(template_content)
+        .map_err(|e| anyhow!("Failed to parse the Handlebars template: {}", e))?;
+
+    let mut referenced = BTreeSet::new();
+    collect_variables(&template, true, &mut referenced);
+    Ok(referenced.contains(name))
+}
+
+/// Recursively collects the top level variable names a template references. The body of the
+/// blocks that change the context, such as `each` and `with`, is left out.
+///
+/// ### Arguments
+///
+/// - `template`: The compiled template.
+/// - `conditions`: Whether the parameters of the `if` and `unless` conditions are collected. When
+///   they aren't, the variables of a condition body that are guarded by the condition itself are
+///   left out too, since the body only renders when they have a value.
+/// - `referenced`: The set the variable names are added to.
+///
+fn collect_variables(template: &Template, conditions: bool, referenced: &mut BTreeSet<String>) {
+    for element in &template.elements {
+        match element {
+            TemplateElement::Expression(helper) | TemplateElement::HtmlExpression(helper) => {
+                collect_parameter(&helper.name, referenced);
+                collect_helper_params(helper, referenced);
+            }
+            TemplateElement::HelperBlock(helper) => {
+                let is_condition = matches!(
+                    &helper.name,
+                    Parameter::Name(name) if matches!(name.as_str(), "if" | "unless")
+                );
+                if conditions || !is_condition {
+                    collect_helper_params(helper, referenced);
+                }
+                if let (true, Some(inner)) = (is_condition, &helper.template) {
+                    let mut body = BTreeSet::new();
+                    collect_variables(inner, conditions, &mut body);
+                    if !conditions {
+                        let mut guarded = BTreeSet::new();
+                        collect_helper_params(helper, &mut guarded);
+                        body.retain(|name| !guarded.contains(name));
+                    }
+                    referenced.extend(body);
+                }
+                if let Some(inverse) = &helper.inverse {
+                    collect_variables(inverse, conditions, referenced);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Collects the variables passed as parameters to a helper.
+fn collect_helper_params(helper: &HelperTemplate, referenced: &mut BTreeSet<String>) {
+    for param in helper.params.iter().chain(helper.hash.values()) {
+        collect_parameter(param, referenced);
+    }
+}
+
+/// Collects the top level variable names of a template parameter, if it is a variable or a
+/// subexpression.
+fn collect_parameter(param: &Parameter, referenced: &mut BTreeSet<String>) {
+    match param {
+        Parameter::Path(TemplatePath::Relative((_, raw))) => {
+            let name = raw.split(['.', '/']).next().unwrap_or_default();
+            if !name.is_empty() && name != "this" && name != ".." {
+                referenced.insert(name.to_owned());
+            }
+        }
+        Parameter::Subexpression(subexpression) => {
+            if let TemplateElement::Expression(helper) = subexpression.as_element() {
+                collect_parameter(&helper.name, referenced);
+                collect_helper_params(helper, referenced);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn template_data() -> Value {
+        json!({
+            "absolute_code_path": "project",
+            "source_tree": "project\n└── main.rs",
+            "files": [
+                { "path": "main.rs", "code": "fn main() {}" },
+                { "path": "empty.rs", "code": "" },
+            ],
+            "git_diff": "",
+        })
+    }
+
+    fn render(data: &Value, format: OutputFormat) -> String {
+        let (template, name) = get_template(&None, format).unwrap();
+        let registry = setup_handlebars_registry(&template, name, format).unwrap();
+        render_output(&registry, name, data, format).unwrap()
+    }
+
+    #[test]
+    fn renders_the_data_as_json() {
+        let data = template_data();
+        let output = render(&data, OutputFormat::JsonStructured);
+        assert_eq!(serde_json::from_str::<Value>(&output).unwrap(), data);
+        assert!(output.starts_with("{\n  \"absolute_code_path\": \"project\","));
+    }
+
+    #[test]
+    fn renders_the_data_as_yaml() {
+        let data = template_data();
+        let output = render(&data, OutputFormat::Yaml);
+        assert_eq!(serde_yaml_ng::from_str::<Value>(&output).unwrap(), data);
+        assert!(output.starts_with("absolute_code_path: project\n"));
+        assert!(output.contains("- code: fn main() {}\n  path: main.rs\n"));
+        assert!(output.contains("source_tree: |-\n  project\n  └── main.rs\n"));
+    }
+
+    #[test]
+    fn renders_the_plain_template() {
+        let output = render(&template_data(), OutputFormat::Plain);
+        assert_eq!(
+            output,
+            "Project Path: project\n\nSource Tree:\n\nproject\n└── main.rs\nCode:\n\nmain.rs:\n\nfn main() {}"
+        );
+    }
+
+    #[test]
+    fn parses_template_vars() {
+        assert_eq!(
+            parse_template_var(" team =core=infra").unwrap(),
+            ("team".to_owned(), "core=infra".to_owned())
+        );
+        assert_eq!(
+            parse_template_var("empty=").unwrap(),
+            ("empty".to_owned(), String::new())
+        );
+        assert!(parse_template_var("team")
+            .unwrap_err()
+            .to_string()
+            .contains("expected KEY=VALUE"));
+        assert!(parse_template_var(" =core")
+            .unwrap_err()
+            .to_string()
+            .contains("the key is empty"));
+    }
+
+    #[test]
+    fn command_line_vars_override_the_vars_file() {
+        let path =
+            std::env::temp_dir().join(format!("codeprompt-vars-{}.toml", std::process::id()));
+        std::fs::write(&path, "team = \"file\"\nticket = \"A-2\"\n").unwrap();
+        let config_vars = json!({ "team": "config", "owner": "config" });
+
+        let merged = merge_template_vars(
+            config_vars.as_object().unwrap(),
+            Some(&path),
+            &[("ticket".to_owned(), "A-3".to_owned())],
+        );
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(
+            Value::Object(merged.unwrap()),
+            json!({ "team": "file", "ticket": "A-3", "owner": "config" })
+        );
+    }
+
+    #[test]
+    fn finds_the_missing_variables() {
+        let template = "{{project}} {{{notes.summary}}} {{#each files}}{{path}}{{/each}} \
+            {{#if ticket}}{{ticket}} {{owner}}{{else}}{{fallback}}{{/if}} {{#with team}}{{name}}{{/with}}";
+        let data = json!({ "project": "codeprompt", "files": [], "owner": null });
+
+        assert_eq!(
+            find_missing_variables(template, &data).unwrap(),
+            ["fallback", "notes", "owner", "team"]
+        );
+        assert!(find_missing_variables("{{#if}", &data).is_err());
+    }
+
+    #[test]
+    fn finds_the_referenced_variables() {
+        let template = "{{#if git_diff}}{{#each files}}{{git_log}}{{/each}}{{/if}}";
+
+        assert!(template_references(template, "git_diff").unwrap());
+        assert!(template_references(template, "files").unwrap());
+        assert!(!template_references(template, "git_log").unwrap());
+    }
+
+    #[test]
+    fn numbers_only_the_rendered_xml_documents() {
+        let data = json!({
+            "absolute_code_path": "project",
+            "files": [
+                { "path": "empty.rs", "code": "" },
+                { "path": "main.rs", "code": "fn main() {}\n" },
+                { "path": "lib.rs", "code": "pub mod a;\n" },
+            ],
+        });
+        let output = render(&data, OutputFormat::Xml);
+
+        assert!(output.contains("<document index=\"1\">\n<source>main.rs</source>"));
+        assert!(output.contains("<document index=\"2\">\n<source>lib.rs</source>"));
+        assert!(!output.contains("empty.rs"));
+        assert!(!output.contains("<issue"));
+    }
+
+    #[test]
+    fn renders_the_issue_in_xml() {
+        let data = json!({
+            "absolute_code_path": "project",
+            "files": [],
+            "github_issue": {
+                "number": 7,
+                "title": "Crash on <empty> input",
+                "body": "Steps & logs",
+                "state": "open",
+                "html_url": "https://github.com/owner/repo/issues/7",
+            },
+        });
+        let output = render(&data, OutputFormat::Xml);
+
+        assert!(output.ends_with(
+            "<issue number=\"7\" state=\"open\">\n\
+             <title>Crash on &lt;empty&gt; input</title>\n\
+             <url>https://github.com/owner/repo/issues/7</url>\n\
+             <body>\nSteps &amp; logs\n</body>\n\
+             </issue>"
+        ));
+        assert!(template_references(XML_TEMPLATE, "github_issue").unwrap());
+        assert!(template_references(XML_TEMPLATE, "files").unwrap());
+    }
+}
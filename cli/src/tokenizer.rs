@@ -2,21 +2,129 @@
 //!
 //! Handles the tokenizer functionality.
 
-use tiktoken_rs::{cl100k_base, CoreBPE};
+use anyhow::{anyhow, Result};
+use tiktoken_rs::tokenizer::{get_tokenizer, Tokenizer};
+use tiktoken_rs::{get_bpe_from_tokenizer, CoreBPE};
+
+/// The supported encodings and the names they can be selected with.
+const ENCODINGS: &[(&str, Tokenizer)] = &[
+    ("cl100k", Tokenizer::Cl100kBase),
+    ("cl100k_base", Tokenizer::Cl100kBase),
+    ("o200k", Tokenizer::O200kBase),
+    ("o200k_base", Tokenizer::O200kBase),
+    ("p50k", Tokenizer::P50kBase),
+    ("p50k_base", Tokenizer::P50kBase),
+    ("p50k_edit", Tokenizer::P50kEdit),
+    ("r50k", Tokenizer::R50kBase),
+    ("r50k_base", Tokenizer::R50kBase),
+];
+
+/// Model name aliases on top of the model names known by tiktoken.
+const MODEL_ALIASES: &[(&str, Tokenizer)] = &[
+    ("gpt-3.5", Tokenizer::Cl100kBase),
+    ("gpt-35", Tokenizer::Cl100kBase),
+    ("gpt-4.1", Tokenizer::O200kBase),
+    ("o1", Tokenizer::O200kBase),
+    ("o3", Tokenizer::O200kBase),
+];
+
+/// Resolves an encoding or model name to the tokenizer it uses.
+///
+/// ### Arguments
+///
+/// - `encoding`: The encoding name (such as `o200k_base`) or model name (such as `gpt-4o`).
+///
+/// ### Returns
+///
+/// - `Result<Tokenizer>`: The tokenizer, or an error listing the valid encodings if the name is
+///   unknown.
+///
+pub fn resolve_encoding(encoding: &str) -> Result<Tokenizer> {
+    let name = encoding.trim().to_lowercase();
+
+    ENCODINGS
+        .iter()
+        .chain(MODEL_ALIASES)
+        .find(|(alias, _)| *alias == name)
+        .map(|(_, tokenizer)| *tokenizer)
+        .or_else(|| get_tokenizer(&name))
+        .ok_or_else(|| {
+            anyhow!(
+                "Unknown encoding '{}'. Valid encodings are: {}. Model names such as gpt-4o, gpt-4 and gpt-3.5 are accepted as well.",
+                encoding,
+                ENCODINGS
+                    .iter()
+                    .map(|(alias, _)| *alias)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })
+}
 
 /// Returns the byte pair encoder to use for tokenization.
 ///
 /// ### Arguments
 ///
-/// - `encoding`: Specifies the encoding to use for the tokenization.
+/// - `encoding`: Specifies the encoding or model name to use for the tokenization.
 ///
 /// ### Returns
 ///
-/// - `CoreBPE`: The tokenizer.
+/// - `Result<CoreBPE>`: The tokenizer, or an error if the encoding is unknown.
 ///
-pub fn tokenizer_init(encoding: &str) -> CoreBPE {
-    match encoding.to_lowercase().trim() {
-        "cl100k" => cl100k_base().unwrap(),
-        _ => cl100k_base().unwrap(),
+pub fn tokenizer_init(encoding: &str) -> Result<CoreBPE> {
+    get_bpe_from_tokenizer(resolve_encoding(encoding)?)
+        .map_err(|e| anyhow!("Failed to initialize the {} tokenizer: {}", encoding, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_encoding_names() {
+        assert_eq!(resolve_encoding("cl100k").unwrap(), Tokenizer::Cl100kBase);
+        assert_eq!(resolve_encoding("o200k").unwrap(), Tokenizer::O200kBase);
+        assert_eq!(resolve_encoding("p50k").unwrap(), Tokenizer::P50kBase);
+        assert_eq!(resolve_encoding("p50k_edit").unwrap(), Tokenizer::P50kEdit);
+        assert_eq!(resolve_encoding("r50k").unwrap(), Tokenizer::R50kBase);
+        assert_eq!(resolve_encoding(" O200K ").unwrap(), Tokenizer::O200kBase);
+    }
+
+    #[test]
+    fn accepts_the_base_suffix() {
+        for name in ["cl100k", "o200k", "p50k", "r50k"] {
+            assert_eq!(
+                resolve_encoding(&format!("{}_base", name)).unwrap(),
+                resolve_encoding(name).unwrap(),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn resolves_model_names() {
+        assert_eq!(resolve_encoding("gpt-3.5").unwrap(), Tokenizer::Cl100kBase);
+        assert_eq!(resolve_encoding("gpt-35").unwrap(), Tokenizer::Cl100kBase);
+        assert_eq!(resolve_encoding("gpt-4.1").unwrap(), Tokenizer::O200kBase);
+        assert_eq!(resolve_encoding("o3").unwrap(), Tokenizer::O200kBase);
+        // Model names known by tiktoken.
+        assert_eq!(resolve_encoding("gpt-4").unwrap(), Tokenizer::Cl100kBase);
+        assert_eq!(resolve_encoding("GPT-4o").unwrap(), Tokenizer::O200kBase);
+    }
+
+    #[test]
+    fn rejects_unknown_encodings() {
+        let error = resolve_encoding("o300k").unwrap_err().to_string();
+        assert!(error.starts_with("Unknown encoding 'o300k'."));
+        assert!(error.contains("Valid encodings are: cl100k, cl100k_base, o200k, o200k_base"));
+
+        assert!(tokenizer_init("o300k").is_err());
+    }
+
+    #[test]
+    fn initializes_the_tokenizer() {
+        let bpe = tokenizer_init("o200k").unwrap();
+        assert_eq!(bpe.encode_with_special_tokens("hello world").len(), 2);
     }
 }
@@ -1,26 +1,94 @@
+use crate::files::SkippedFile;
+use crate::git::{DiffSource, LogSource};
+use crate::secrets::SecretFinding;
+use crate::template::{template_references, OutputFormat};
 use colored::*;
-use std::path::PathBuf;
 use git2::Repository;
 use std::io::{self, Write};
+use std::path::PathBuf;
 
-/// Token count threshold for warning.
-const TOKEN_WARNING_THRESHOLD: usize = 30_000;
+/// Default token count threshold for warning.
+pub const TOKEN_WARNING_THRESHOLD: usize = 30_000;
 
-/// Token count threshold for clipboard safety prompt.
-const CLIPBOARD_TOKEN_THRESHOLD: usize = 200_000;
+/// Default token count threshold for clipboard safety prompt.
+pub const CLIPBOARD_TOKEN_THRESHOLD: usize = 200_000;
+
+/// Exit code for errors that aren't validation errors.
+pub const EXIT_FAILURE: i32 = 1;
 
 /// Represents different types of validation warnings.
 #[derive(Debug)]
 pub enum ValidationWarning {
-    /// Warning for when a git diff option is used without a template.
+    /// Warning for when a git diff option is used with a template that doesn't render the diff.
     GitDiffNoTemplate,
-    /// Warning for when the git issue option is used without a template.
+    /// Warning for when a git log option is used with a template that doesn't render the log.
+    GitLogNoTemplate,
+    /// Warning for when the git issue option is used with a template that doesn't render the
+    /// issue.
     IssueNoTemplate,
     /// Warning for when token count is high.
     LargeTokenCount(usize),
+    /// Warning for when files were left out to stay within the token budget.
+    FilesOmitted {
+        /// The token budget.
+        max_tokens: usize,
+        /// The paths of the omitted files.
+        omitted: Vec<String>,
+    },
+    /// Warning for when the template references variables that have no value.
+    MissingTemplateVariables(Vec<String>),
+    /// Warning for when files were skipped because they are binary, too large or generated.
+    FilesSkipped(Vec<SkippedFile>),
+    /// Warning for when symbols passed with --symbol weren't found in any file.
+    SymbolsNotFound(Vec<String>),
+    /// Warning for when the prompt couldn't be copied to the clipboard.
+    ClipboardFailed(String),
+    /// Warning for when possible secrets were found in the file contents.
+    SecretsDetected {
+        /// Whether the secrets were redacted.
+        redacted: bool,
+        /// The secrets found.
+        findings: Vec<SecretFinding>,
+    },
+    /// Warning for when sensitive files were included without a confirmation prompt.
+    SensitiveFiles(Vec<String>),
+    /// Warning for when files were left out because their line ranges are past the end of the
+    /// file.
+    LineRangesOutOfBounds(Vec<String>),
 }
 
 impl ValidationWarning {
+    /// The process exit code of the warning with --fail-on-warnings or --fail-on-sensitive.
+    ///
+    /// The skipped files report is informational and has no exit code, since most repositories
+    /// contain some binary files.
+    pub fn exit_code(&self) -> Option<i32> {
+        match self {
+            Self::GitDiffNoTemplate => Some(10),
+            Self::GitLogNoTemplate => Some(11),
+            Self::IssueNoTemplate => Some(12),
+            Self::LargeTokenCount(_) => Some(13),
+            Self::FilesOmitted { .. } => Some(14),
+            Self::MissingTemplateVariables(_) => Some(15),
+            Self::FilesSkipped(_) => None,
+            Self::SymbolsNotFound(_) => Some(16),
+            Self::ClipboardFailed(_) => Some(17),
+            Self::SecretsDetected { .. } => Some(18),
+            Self::SensitiveFiles(_) => Some(19),
+            Self::LineRangesOutOfBounds(_) => Some(20),
+        }
+    }
+
+    /// Whether the warning is about sensitive data left in the prompt, which fails the run with
+    /// --fail-on-sensitive.
+    pub fn is_sensitive(&self) -> bool {
+        match self {
+            Self::SecretsDetected { redacted, .. } => !redacted,
+            Self::SensitiveFiles(_) => true,
+            _ => false,
+        }
+    }
+
     fn prefix() -> String {
         format!(
             "{}{}{} ",
@@ -38,18 +106,83 @@ impl ValidationWarning {
             Self::GitDiffNoTemplate => format!(
                 "{}{}", 
                 prefix, 
-                "Git diff option used without a template. Consider using --template with some git template.".yellow()
+                "Git diff option used with a template that doesn't render the git diff. Consider using --template with some git template.".yellow()
+            ),
+            Self::GitLogNoTemplate => format!(
+                "{}{}",
+                prefix,
+                "Git log option used with a template that doesn't render the git log. Consider using --template with some git template.".yellow()
             ),
             Self::IssueNoTemplate => format!(
                 "{}{}", 
                 prefix, 
-                "Issue option used without a template.Consider using --template with some git template.".yellow()
+                "Issue option used with a template that doesn't render the issue. Consider using --template with some git template.".yellow()
             ),
             Self::LargeTokenCount(count) => format!(
                 "{}{}", 
                 prefix, 
                 format!("Large token count ({}). You might want to consider using the --output option to write to a file instead of the clipboard", count).yellow(),
             ),
+            Self::FilesOmitted { max_tokens, omitted } => format!(
+                "{}{}{}",
+                prefix,
+                format!("{} file(s) left out to stay within the {} token budget:", omitted.len(), max_tokens).yellow(),
+                omitted
+                    .iter()
+                    .map(|path| format!("\n  - {}", path))
+                    .collect::<String>(),
+            ),
+            Self::MissingTemplateVariables(names) => format!(
+                "{}{}",
+                prefix,
+                format!("Template variables without a value: {}. Pass them with --var or --vars-file, they will render as empty strings.", names.join(", ")).yellow(),
+            ),
+            Self::FilesSkipped(skipped) => format!(
+                "{}{}{}",
+                prefix,
+                format!("{} file(s) skipped:", skipped.len()).yellow(),
+                skipped
+                    .iter()
+                    .map(|file| format!("\n  - {} ({})", file.path, file.reason))
+                    .collect::<String>(),
+            ),
+            Self::SymbolsNotFound(symbols) => format!(
+                "{}{}",
+                prefix,
+                format!("Symbols not found in the included files: {}", symbols.join(", ")).yellow(),
+            ),
+            Self::ClipboardFailed(error) => format!(
+                "{}{}",
+                prefix,
+                format!("Failed to copy the prompt to the clipboard: {}. Use --output to write it to a file or --clipboard-backend to pick a backend.", error).yellow(),
+            ),
+            Self::SecretsDetected { redacted, findings } => format!(
+                "{}{}{}",
+                prefix,
+                if *redacted {
+                    format!("{} possible secret(s) redacted:", findings.len()).yellow()
+                } else {
+                    format!("{} possible secret(s) detected. Consider using --redact to replace them:", findings.len()).yellow()
+                },
+                findings
+                    .iter()
+                    .map(|finding| format!("\n  - {}:{} ({})", finding.path, finding.line, finding.rule))
+                    .collect::<String>(),
+            ),
+            Self::SensitiveFiles(files) => format!(
+                "{}{}{}",
+                prefix,
+                format!("{} sensitive file(s) included, these files may contain secrets or credentials:", files.len()).yellow(),
+                files
+                    .iter()
+                    .map(|file| format!("\n  - {}", file))
+                    .collect::<String>(),
+            ),
+            Self::LineRangesOutOfBounds(files) => format!(
+                "{}{}",
+                prefix,
+                format!("Files left out because their line ranges are past the end of the file: {}", files.join(", ")).yellow(),
+            ),
         }
     }
 }
@@ -61,6 +194,13 @@ pub enum ValidationError {
 }
 
 impl ValidationError {
+    /// The process exit code of the error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::NoGitRepo => 2,
+        }
+    }
+
     fn prefix() -> String {
         format!(
             "{}{}{} ",
@@ -85,34 +225,54 @@ impl ValidationError {
 /// Configuration options to validate.
 #[derive(Debug)]
 pub struct ValidationConfig<'a> {
-    pub diff_staged: bool,
-    pub diff_unstaged: bool,
+    pub diff_source: &'a Option<DiffSource>,
+    pub log_source: &'a Option<LogSource>,
     pub issue: Option<u32>,
-    pub template: &'a Option<PathBuf>,
+    /// The content of the selected template.
+    pub template: &'a str,
+    pub format: OutputFormat,
 }
 
 impl<'a> ValidationConfig<'a> {
     /// Constructor.
-    pub fn new(diff_staged: bool, diff_unstaged: bool, issue: Option<u32>, template: &'a Option<PathBuf>) -> Self {
+    pub fn new(
+        diff_source: &'a Option<DiffSource>,
+        log_source: &'a Option<LogSource>,
+        issue: Option<u32>,
+        template: &'a str,
+        format: OutputFormat,
+    ) -> Self {
         Self {
-            diff_staged,
-            diff_unstaged,
+            diff_source,
+            log_source,
             issue,
             template,
+            format,
         }
     }
 
     /// Performs the validation logic.
     pub fn validate(&self) -> Vec<ValidationWarning> {
         let mut warnings = Vec::new();
+        // The structured formats include all of the template data, and the other formats only
+        // include the variables their template renders.
+        let renders = |name: &str| {
+            self.format.is_structured()
+                || template_references(self.template, name).unwrap_or(false)
+        };
 
-        // Check for git diff options without template
-        if (self.diff_staged || self.diff_unstaged) && self.template.is_none() {
+        // Check for git diff options without a template that renders the diff
+        if self.diff_source.is_some() && !renders("git_diff") {
             warnings.push(ValidationWarning::GitDiffNoTemplate);
         }
 
-        // Check for issue option without template
-        if self.issue.is_some() && self.template.is_none() {
+        // Check for git log options without a template that renders the log
+        if self.log_source.is_some() && !renders("git_log") {
+            warnings.push(ValidationWarning::GitLogNoTemplate);
+        }
+
+        // Check for issue option without a template that renders the issue
+        if self.issue.is_some() && !renders("github_issue") {
             warnings.push(ValidationWarning::IssueNoTemplate);
         }
 
@@ -121,7 +281,8 @@ impl<'a> ValidationConfig<'a> {
 
     /// Validates git repository presence when git features used.
     pub fn validate_git_repo(&self, path: &PathBuf) -> Result<(), ValidationError> {
-        if (self.diff_staged || self.diff_unstaged || self.issue.is_some()) && !Repository::open(path).is_ok() {
+        let uses_git = self.diff_source.is_some() || self.log_source.is_some() || self.issue.is_some();
+        if uses_git && Repository::open(path).is_err() {
             Err(ValidationError::NoGitRepo)
         } else {
             Ok(())
@@ -133,11 +294,12 @@ impl<'a> ValidationConfig<'a> {
 ///
 /// ### Parameters
 /// - `token_count`: The number of tokens to check.
+/// - `threshold`: The token count above which the warning is returned.
 ///
 /// ### Returns
 /// - `Option<ValidationWarning>`: The warning, if applicable.
-pub fn validate_token_count(token_count: usize) -> Option<ValidationWarning> {
-    if token_count > TOKEN_WARNING_THRESHOLD {
+pub fn validate_token_count(token_count: usize, threshold: usize) -> Option<ValidationWarning> {
+    if token_count > threshold {
         Some(ValidationWarning::LargeTokenCount(token_count))
     } else {
         None
@@ -150,20 +312,28 @@ pub fn validate_token_count(token_count: usize) -> Option<ValidationWarning> {
 /// ### Arguments
 ///
 /// - `token_count`: The number of tokens in the output.
+/// - `threshold`: The token count above which the user is prompted.
 /// - `no_warnings`: Whether to skip all warnings and prompts.
+/// - `interactive`: Whether the user can be prompted. Without a prompt, the copy doesn't proceed
+///   above the threshold.
 ///
 /// ### Returns
 ///
 /// - `bool`: True if clipboard copy should proceed, False otherwise.
 ///
-pub fn validate_clipboard_copy(token_count: usize, no_warnings: bool) -> bool {
+pub fn validate_clipboard_copy(
+    token_count: usize,
+    threshold: usize,
+    no_warnings: bool,
+    interactive: bool,
+) -> bool {
     // TODO : This is hacky, should probably handle this directly in main
     if no_warnings {
         return true
     }
 
-    if token_count > CLIPBOARD_TOKEN_THRESHOLD {
-        prompt_for_large_clipboard(token_count)
+    if token_count > threshold {
+        interactive && prompt_for_large_clipboard(token_count, threshold)
     } else {
         true
     }
@@ -174,12 +344,13 @@ pub fn validate_clipboard_copy(token_count: usize, no_warnings: bool) -> bool {
 /// ### Arguments
 ///
 /// - `token_count`: The number of tokens in the output.
+/// - `threshold`: The clipboard token threshold.
 ///
 /// ### Returns
 ///
 /// - `bool`: True if user confirms to copy, False otherwise.
 ///
-fn prompt_for_large_clipboard(token_count: usize) -> bool {
+fn prompt_for_large_clipboard(token_count: usize, threshold: usize) -> bool {
     eprintln!(
         "\n{}{}{} {}",
         "[".bold().white(),
@@ -191,7 +362,7 @@ fn prompt_for_large_clipboard(token_count: usize) -> bool {
     eprintln!(
         "  Token count: {} (threshold: {})",
         token_count.to_string().red(),
-        CLIPBOARD_TOKEN_THRESHOLD.to_string().yellow()
+        threshold.to_string().yellow()
     );
 
     eprintln!(
@@ -199,10 +370,104 @@ fn prompt_for_large_clipboard(token_count: usize) -> bool {
         "Copying this much data to clipboard may cause system issues.".yellow()
     );
     eprint!("{}", "Copy to clipboard anyway? [y/N] ".bold());
-    io::stdout().flush().unwrap();
+    io::stderr().flush().unwrap();
 
     let mut response = String::new();
     io::stdin().read_line(&mut response).unwrap();
 
     matches!(response.trim().to_lowercase().as_str(), "y" | "yes")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::SkipReason;
+    use crate::template::find_builtin_template;
+
+    fn all_warnings() -> Vec<ValidationWarning> {
+        vec![
+            ValidationWarning::GitDiffNoTemplate,
+            ValidationWarning::GitLogNoTemplate,
+            ValidationWarning::IssueNoTemplate,
+            ValidationWarning::LargeTokenCount(100_000),
+            ValidationWarning::FilesOmitted {
+                max_tokens: 1000,
+                omitted: vec!["a.rs".to_owned()],
+            },
+            ValidationWarning::MissingTemplateVariables(vec!["ticket".to_owned()]),
+            ValidationWarning::SymbolsNotFound(vec!["main".to_owned()]),
+            ValidationWarning::ClipboardFailed("no display".to_owned()),
+            ValidationWarning::SecretsDetected {
+                redacted: false,
+                findings: Vec::new(),
+            },
+            ValidationWarning::SensitiveFiles(vec![".env".to_owned()]),
+            ValidationWarning::LineRangesOutOfBounds(vec!["src/lib.rs".to_owned()]),
+        ]
+    }
+
+    #[test]
+    fn warnings_have_distinct_exit_codes() {
+        let codes: Vec<i32> = all_warnings()
+            .iter()
+            .map(|warning| warning.exit_code().unwrap())
+            .collect();
+
+        assert_eq!(codes, vec![10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20]);
+        assert!(codes
+            .iter()
+            .all(|code| *code != EXIT_FAILURE && *code != ValidationError::NoGitRepo.exit_code()));
+    }
+
+    #[test]
+    fn skipped_files_report_has_no_exit_code() {
+        let warning = ValidationWarning::FilesSkipped(vec![SkippedFile {
+            path: "logo.png".to_owned(),
+            reason: SkipReason::Binary,
+        }]);
+
+        assert_eq!(warning.exit_code(), None);
+    }
+
+    #[test]
+    fn only_unredacted_secrets_and_sensitive_files_are_sensitive() {
+        let sensitive: Vec<bool> = all_warnings()
+            .iter()
+            .map(ValidationWarning::is_sensitive)
+            .collect();
+        assert_eq!(sensitive.iter().filter(|sensitive| **sensitive).count(), 2);
+
+        let redacted = ValidationWarning::SecretsDetected {
+            redacted: true,
+            findings: Vec::new(),
+        };
+        assert!(!redacted.is_sensitive());
+    }
+
+    #[test]
+    fn warns_about_git_options_only_when_the_template_doesnt_render_them() {
+        let diff = Some(DiffSource::Staged);
+        let log = Some(LogSource::Recent(5));
+        let default = find_builtin_template("default").unwrap().content;
+        let optimization = find_builtin_template("code_optimization").unwrap().content;
+
+        let config = ValidationConfig::new(&diff, &None, None, default, OutputFormat::Markdown);
+        assert!(config.validate().is_empty());
+
+        let config =
+            ValidationConfig::new(&diff, &log, Some(1), optimization, OutputFormat::Markdown);
+        let warnings = config.validate();
+        assert!(matches!(
+            warnings.as_slice(),
+            [
+                ValidationWarning::GitDiffNoTemplate,
+                ValidationWarning::GitLogNoTemplate,
+                ValidationWarning::IssueNoTemplate
+            ]
+        ));
+
+        // The structured formats include all of the template data.
+        let config = ValidationConfig::new(&diff, &log, Some(1), optimization, OutputFormat::Yaml);
+        assert!(config.validate().is_empty());
+    }
+}
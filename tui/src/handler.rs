@@ -64,7 +64,7 @@ fn handle_button_input(app: &mut App, key: KeyEvent) -> Result<()> {
     Ok(())
 }
 
-/// Panel Navigation
+// Panel Navigation
 
 fn navigate_left(app: &mut App) {
     app.active_panel = match app.active_panel {
@@ -99,7 +99,7 @@ fn navigate_down(app: &mut App) {
     }
 }
 
-/// Button Interaction
+// Button Interaction
 
 fn focus_next_button(app: &mut App) {
     app.focused_button = (app.focused_button + 1) % 4;
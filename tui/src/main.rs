@@ -7,8 +7,8 @@ fn main() -> Result<()> {
 
     let mut app = App::new(config)?;
     if let Some(cmd) = app.run()? {
-        if cmd.starts_with('!') {
-            println!("{}", &cmd[1..]);
+        if let Some(printed) = cmd.strip_prefix('!') {
+            println!("{}", printed);
         } else {
             println!("Command: {}", &cmd);
             let args = app.construct_command_args();
@@ -127,8 +127,8 @@ impl Panel for OptionsPanel {
         if self.options.no_codeblock {
             args.push("--no-codeblock".to_owned());
         }
-        if self.options.relative_paths {
-            args.push("--relative_paths".to_owned());
+        if self.options.absolute_paths {
+            args.push("--relative-paths".to_owned());
         }
         if self.options.no_clipboard {
             args.push("--no-clipboard".to_owned());
@@ -133,7 +133,7 @@ impl Panel for TemplatesPanel {
             let is_selected = self
                 .selected_template
                 .as_ref()
-                .map_or(false, |selected| selected == path);
+                .is_some_and(|selected| selected == path);
 
             let name = path
                 .file_name()
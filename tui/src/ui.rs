@@ -7,6 +7,7 @@ use ratatui::Frame;
 
 const BUTTON_LABELS: [&str; 4] = ["Run", "Print", "Reset", "Exit"];
 
+#[allow(clippy::too_many_arguments)]
 pub fn draw(
     frame: &mut Frame,
     command: &str,